@@ -1,60 +1,444 @@
-use std::time::{Instant};
+use std::rc::Rc;
+use std::time::Duration;
 
 use windows::Win32::UI::WindowsAndMessaging::{
     DispatchMessageW, GetMessageW, PeekMessageW, TranslateMessage, MSG, PM_REMOVE, WM_QUIT,
 };
 pub type Result<T> = core::result::Result<T, Win32Error>;
-use crate::{window::Window, error::Win32Error};
+use crate::{
+    application::Application,
+    error::Win32Error,
+    event::WindowEvent,
+    timing::{Clock, FixedTimestep, FrameLimiter, FrameStats, SystemClock, TimerResolution},
+    window::{Window, WindowId},
+    window_builder::WindowBuilder,
+};
+
+/// Default rate of `Application::fixed_update` calls per second.
+pub const DEFAULT_FIXED_UPDATE_RATE: f64 = 60.0;
+/// Default limit of fixed updates run in a single frame.
+pub const DEFAULT_MAX_FIXED_STEPS: u32 = 8;
+/// Number of recent frames covered by `App::frame_stats`.
+pub const FRAME_STATS_WINDOW: usize = 240;
+/// Frame rate of `BackgroundBehavior::default()`.
+pub const DEFAULT_BACKGROUND_FPS: f64 = 10.0;
+
+/// What the loop does while the application is in the background, i.e. no window
+/// that can be seen has keyboard focus.
+///
+/// Minimized and occluded windows are never rendered, whatever the behavior.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum BackgroundBehavior {
+    /// Stop updating and rendering and block until a window message arrives.
+    Pause,
+    /// Keep updating and rendering at no more than the given frame rate. A rate that is
+    /// not positive pauses instead.
+    Throttle(f64),
+    /// Run exactly as in the foreground, only limited by `App::set_target_fps`.
+    Continue,
+}
+
+impl Default for BackgroundBehavior {
+    fn default() -> Self {
+        BackgroundBehavior::Throttle(DEFAULT_BACKGROUND_FPS)
+    }
+}
 
 pub struct App {
-    window: Window,
-    init_time: Instant,
+    windows: Vec<Window>,
+    clock: Rc<dyn Clock>,
+    timestep: FixedTimestep,
+    limiter: FrameLimiter,
+    background: BackgroundBehavior,
+    background_limiter: FrameLimiter,
+    frame_stats: FrameStats,
+    stats_log_interval: Option<Duration>,
 }
 
 impl App {
     pub fn new() -> App {
         App {
-            window: Window::new(800, 600, "-"),
-            init_time: Instant::now(),
+            windows: Vec::new(),
+            clock: Rc::new(SystemClock::new()),
+            timestep: FixedTimestep::from_rate(DEFAULT_FIXED_UPDATE_RATE, DEFAULT_MAX_FIXED_STEPS),
+            limiter: FrameLimiter::new(None),
+            background: BackgroundBehavior::default(),
+            background_limiter: FrameLimiter::new(Some(DEFAULT_BACKGROUND_FPS)),
+            frame_stats: FrameStats::new(FRAME_STATS_WINDOW),
+            stats_log_interval: None,
         }
     }
 
-    pub fn run(&mut self) -> Result<()> {
-        self.window.initialize()?;
-        let mut message = MSG::default();
+    /// Replaces the time source of the loop, e.g. with a `ManualClock` for tests. The
+    /// caller keeps a clone of `clock` to advance it.
+    pub fn set_clock(&mut self, clock: Rc<dyn Clock>) {
+        self.clock = clock;
+        self.timestep.reset();
+    }
+
+    pub fn fixed_timestep(&self) -> &FixedTimestep {
+        &self.timestep
+    }
+
+    pub fn set_fixed_timestep(&mut self, timestep: FixedTimestep) {
+        self.timestep = timestep;
+    }
+
+    pub fn target_fps(&self) -> Option<f64> {
+        self.limiter.target_fps()
+    }
+
+    /// Caps the frame rate on top of any vsync; `None` removes the cap.
+    pub fn set_target_fps(&mut self, target_fps: Option<f64>) {
+        self.limiter.set_target_fps(target_fps);
+    }
+
+    pub fn background_behavior(&self) -> BackgroundBehavior {
+        self.background
+    }
+
+    pub fn set_background_behavior(&mut self, background: BackgroundBehavior) {
+        self.background = match background {
+            // The limiter would take these rates as no cap at all
+            BackgroundBehavior::Throttle(fps) if fps.is_nan() || fps <= 0.0 => {
+                BackgroundBehavior::Pause
+            }
+            background => background,
+        };
+        if let BackgroundBehavior::Throttle(fps) = self.background {
+            self.background_limiter.set_target_fps(Some(fps));
+        }
+    }
+
+    /// Whether no window that can be seen has keyboard focus.
+    pub fn is_in_background(&self) -> bool {
+        !self
+            .windows
+            .iter()
+            .any(|window| window.is_focused() && window.can_render())
+    }
+
+    /// Frame times of the most recent frames, measured between frame starts.
+    pub fn frame_stats(&self) -> &FrameStats {
+        &self.frame_stats
+    }
+
+    /// Prints the frame statistics every `interval` while running; `None` turns it off.
+    pub fn set_frame_stats_logging(&mut self, interval: Option<Duration>) {
+        self.stats_log_interval = interval;
+    }
+
+    /// Creates the OS window for `window` and adds it to the application.
+    pub fn add_window(&mut self, window: Window) -> Result<WindowId> {
+        let window = Window::create(window)?;
+        let id = window.id();
+        self.windows.push(window);
+        Ok(id)
+    }
+
+    pub fn window(&self, id: WindowId) -> Option<&Window> {
+        self.windows.iter().find(|window| window.id() == id)
+    }
+
+    pub fn window_mut(&mut self, id: WindowId) -> Option<&mut Window> {
+        self.windows.iter_mut().find(|window| window.id() == id)
+    }
+
+    /// Runs `application` until the last main window is closed, then closes the
+    /// remaining windows.
+    ///
+    /// An error from `application` or from handling a window message ends the run and is
+    /// returned; a panic while handling a window message is resumed. Closing the windows
+    /// is reported the same way, unless the run already failed.
+    pub fn run<A: Application>(&mut self, application: &mut A) -> Result<()> {
+        let result = application
+            .init(self)
+            .and_then(|()| self.run_loop(application));
+        application.shutdown(self);
+        let closed = self.close_windows();
+        self.windows.clear();
+        result.and(closed)
+    }
+
+    /// Closes all windows, failing with the first error, also one raised while handling
+    /// the messages of the teardown, e.g. saving the placement on `WM_DESTROY`.
+    fn close_windows(&mut self) -> Result<()> {
+        // Each window releases its graphics before destroying its OS window
+        let closed = self
+            .windows
+            .iter_mut()
+            .map(Window::close)
+            .fold(Ok(()), Result::and);
+        closed.and(self.take_message_failures())
+    }
+
+    fn run_loop<A: Application>(&mut self, application: &mut A) -> Result<()> {
+        let _timer_resolution = TimerResolution::new();
+        let mut last_update = self.clock.now();
+        let mut last_stats_log = last_update;
+        let mut paused = false;
+        let mut block = false;
+        self.timestep.restart(last_update);
         loop {
-            unsafe {
-                // Initially the window is not visible
-                if self.window.visible {
-                    
-                    while PeekMessageW(&mut message, None, 0, 0, PM_REMOVE).into() {
-                        if message.message == WM_QUIT {
-                            return Ok(());
-                        }
-                        TranslateMessage(&message);
-                        DispatchMessageW(&message);
+            if !self.pump_messages(block)? {
+                return Ok(());
+            }
+            // Handling an event can cause more, such as `Destroyed` after a close, so
+            // drain until the queues stay empty
+            loop {
+                let events = self.poll_events();
+                if events.is_empty() {
+                    break;
+                }
+                for (id, event) in events {
+                    if let Some(window) = self.window_mut(id) {
+                        Self::dispatch_event(application, window, &event)?;
                     }
-                    self.render()?;
-                } else {
-                    GetMessageW(&mut message, None, 0, 0);
+                }
+            }
+            // Destroyed windows report what went wrong while they were torn down first
+            self.take_message_failures()?;
+            self.windows.retain(|window| !window.is_destroyed());
+            if !self.windows.iter().any(|window| window.is_main()) {
+                return Ok(());
+            }
 
-                    if message.message == WM_QUIT {
-                        return Ok(());
-                    }
-                    TranslateMessage(&message);
-                    DispatchMessageW(&message);
+            let in_background = self.is_in_background();
+            if in_background && self.background == BackgroundBehavior::Pause {
+                // Occluded windows get no message when they become visible again, so
+                // keep probing them at the background rate instead of blocking
+                paused = true;
+                let still_occluded = self.probe_occluded()?;
+                block = !still_occluded && self.is_in_background();
+                if still_occluded {
+                    let wait_time = self.background_limiter.wait_time(self.clock.now());
+                    self.clock.sleep(wait_time);
+                }
+                continue;
+            }
+            block = false;
+            if paused {
+                // Resume from now instead of catching up on the time spent paused
+                paused = false;
+                last_update = self.clock.now();
+                self.timestep.restart(last_update);
+            }
+
+            let limiter = if in_background && self.background != BackgroundBehavior::Continue {
+                &mut self.background_limiter
+            } else {
+                &mut self.limiter
+            };
+            let wait_time = limiter.wait_time(self.clock.now());
+            if !wait_time.is_zero() {
+                self.clock.sleep(wait_time);
+            }
+
+            let now = self.clock.now();
+            let delta_time = now.saturating_sub(last_update);
+            last_update = now;
+            self.frame_stats.record(delta_time);
+            if let Some(interval) = self.stats_log_interval {
+                if now.saturating_sub(last_stats_log) >= interval {
+                    println!("{}", self.frame_stats);
+                    last_stats_log = now;
+                }
+            }
+
+            application.update(self, delta_time)?;
+
+            let steps = self.timestep.advance(now);
+            let step = self.timestep.step();
+            for _ in 0..steps.count {
+                application.fixed_update(self, step)?;
+            }
+
+            self.render(application, steps.alpha)?;
+        }
+    }
+
+    /// Dispatches the pending Win32 messages to the window procedures. With `block` set,
+    /// or while no window is visible, waits for at least one message first. Returns
+    /// `false` once WM_QUIT is received.
+    ///
+    /// Fails with the first error a window procedure raised, and resumes a panic caught
+    /// in one, now that no OS frames are on the stack.
+    pub fn pump_messages(&mut self, block: bool) -> Result<bool> {
+        let running = self.dispatch_messages(block);
+        self.take_message_failures()?;
+        Ok(running)
+    }
+
+    fn take_message_failures(&mut self) -> Result<()> {
+        for window in self.windows.iter_mut() {
+            window.take_message_failure()?;
+        }
+        Ok(())
+    }
+
+    fn dispatch_messages(&mut self, block: bool) -> bool {
+        let mut message = MSG::default();
+        unsafe {
+            if block || !self.windows.iter().any(|window| window.is_visible()) {
+                GetMessageW(&mut message, None, 0, 0);
+
+                if message.message == WM_QUIT {
+                    return false;
+                }
+                TranslateMessage(&message);
+                DispatchMessageW(&message);
+            }
+            while PeekMessageW(&mut message, None, 0, 0, PM_REMOVE).into() {
+                if message.message == WM_QUIT {
+                    return false;
+                }
+                TranslateMessage(&message);
+                DispatchMessageW(&message);
+            }
+        }
+        true
+    }
+
+    /// Drains the event queues of all windows, tagging each event with its window.
+    pub fn poll_events(&mut self) -> Vec<(WindowId, WindowEvent)> {
+        let mut events = Vec::new();
+        for window in self.windows.iter_mut() {
+            let id = window.id();
+            events.extend(window.events().map(|event| (id, event)));
+        }
+        events
+    }
+
+    fn dispatch_event<A: Application>(
+        application: &mut A,
+        window: &mut Window,
+        event: &WindowEvent,
+    ) -> Result<()> {
+        Self::handle_window_event(window, event)?;
+        if let WindowEvent::Resized { width, height } = *event {
+            application.on_resize(window, width, height)?;
+        }
+        application.on_event(window, event)?;
+        if let WindowEvent::CloseRequested = event {
+            if application.on_close_requested(window)? {
+                window.close()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn handle_window_event(window: &mut Window, event: &WindowEvent) -> Result<()> {
+        match event {
+            WindowEvent::Resized { width, height } => {
+                if let Some(mut gfx) = window.graphics_mut() {
+                    gfx.resize(*width as u32, *height as u32)?;
                 }
             }
+            WindowEvent::FullscreenToggleRequested => window.toggle_fullscreen()?,
+            _ => {}
         }
+        Ok(())
     }
 
-    fn render(&mut self) -> Result<()> {
-        let now = Instant::now().duration_since(self.init_time).as_secs_f32();
-        let c = f32::sin(now) / 2.0 + 0.5;
-        let gfx = self.window.gfx.as_mut().unwrap();
-        gfx.clear_buffer(c / 1.2, 1.0 - c / 1.5,  1.0 - c /1.2);
-        gfx.draw_test_triangle();
-        gfx.present_frame()?;
+    fn render<A: Application>(&mut self, application: &mut A, alpha: f32) -> Result<()> {
+        self.probe_occluded()?;
+        for window in self.windows.iter_mut().filter(|window| window.can_render()) {
+            application.render(window, alpha)?;
+            let presented = match window.graphics() {
+                Some(gfx) => gfx.present_frame()?,
+                None => true,
+            };
+            window.set_occluded(!presented);
+        }
         Ok(())
     }
+
+    /// Checks whether occluded windows became visible again. Returns `true` if any
+    /// window is still occluded.
+    fn probe_occluded(&mut self) -> Result<bool> {
+        let mut any_occluded = false;
+        for window in self.windows.iter_mut().filter(|window| window.is_occluded()) {
+            let visible = match window.graphics() {
+                Some(gfx) => gfx.test_present()?,
+                None => true,
+            };
+            window.set_occluded(!visible);
+            any_occluded |= !visible;
+        }
+        Ok(any_occluded)
+    }
+}
+
+impl Default for App {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Creates the main window from `main_window` and runs `application` until it closes.
+pub fn run<A: Application>(main_window: WindowBuilder, mut application: A) -> Result<()> {
+    let mut app = App::new();
+    app.add_window(main_window.build()?)?;
+    app.run(&mut application)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::panic::{self, AssertUnwindSafe};
+
+    use windows::Win32::Foundation::E_INVALIDARG;
+    use windows::Win32::UI::WindowsAndMessaging::{WM_CHAR, WM_USER};
+
+    use super::*;
+    use crate::message_hook::HookResult;
+
+    // Not a UTF-16 code unit, which fails the handling of WM_CHAR
+    const INVALID_CHAR: usize = 0x1_0000;
+
+    fn app_with_windows(count: usize) -> App {
+        let mut app = App::new();
+        for _ in 0..count {
+            app.windows.push(Window::new(640, 480, "test"));
+        }
+        app
+    }
+
+    #[test]
+    fn non_positive_throttle_rates_pause() {
+        let mut app = App::new();
+        for fps in [0.0, -10.0, f64::NAN] {
+            app.set_background_behavior(BackgroundBehavior::Throttle(fps));
+            assert_eq!(app.background_behavior(), BackgroundBehavior::Pause);
+        }
+        app.set_background_behavior(BackgroundBehavior::Throttle(30.0));
+        assert_eq!(app.background_behavior(), BackgroundBehavior::Throttle(30.0));
+    }
+
+    #[test]
+    fn teardown_returns_the_first_message_error() {
+        let mut app = app_with_windows(3);
+        app.windows[1].handle_test_message(WM_CHAR, INVALID_CHAR, 0);
+        app.windows[2].handle_test_message(WM_CHAR, INVALID_CHAR, 0);
+        let closed = app.close_windows();
+        assert_eq!(closed.err().map(|e| e.code()), Some(E_INVALIDARG));
+
+        // The error was taken, the one of the next window is still pending
+        let closed = app.close_windows();
+        assert_eq!(closed.err().map(|e| e.code()), Some(E_INVALIDARG));
+        assert!(app.close_windows().is_ok());
+    }
+
+    #[test]
+    fn teardown_resumes_a_message_panic() {
+        let mut app = app_with_windows(2);
+        app.windows[0].add_message_hook(|_| -> HookResult { panic!("handler failed") });
+        assert_eq!(app.windows[0].handle_test_message(WM_USER, 0, 0), None);
+        // Messages after the panic get the default handling until it is resumed
+        let handled = app.windows[0].handle_test_message(WM_CHAR, 'a' as usize, 0);
+        assert_eq!(handled, None);
+
+        let payload = panic::catch_unwind(AssertUnwindSafe(|| app.close_windows())).unwrap_err();
+        assert_eq!(payload.downcast_ref::<&str>(), Some(&"handler failed"));
+        assert!(app.close_windows().is_ok());
+    }
 }
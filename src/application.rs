@@ -0,0 +1,58 @@
+use std::time::Duration;
+
+use crate::app::App;
+use crate::error::Win32Error;
+use crate::event::WindowEvent;
+use crate::window::Window;
+pub type Result<T> = core::result::Result<T, Win32Error>;
+
+/// User code driven by the `App` runner.
+///
+/// The runner owns the windows and their graphics, pumps the Win32 messages and calls
+/// these hooks in order: `init` once, then per frame `on_resize`/`on_event` for each
+/// window event, `update`, `fixed_update` as often as the fixed timestep requires and
+/// `render` for every window that can be seen, and `shutdown` once the last main window
+/// closed or a hook returned an error. See `BackgroundBehavior` for how the loop runs
+/// while the application is in the background.
+pub trait Application {
+    /// Called after the windows added before `App::run` were created. More windows
+    /// can be opened with `App::add_window`.
+    fn init(&mut self, _app: &mut App) -> Result<()> {
+        Ok(())
+    }
+
+    /// Advances the application by the wall-clock time since the previous update.
+    fn update(&mut self, _app: &mut App, _delta_time: Duration) -> Result<()> {
+        Ok(())
+    }
+
+    /// Advances the simulation by exactly one fixed step, see `App::set_fixed_timestep`.
+    fn fixed_update(&mut self, _app: &mut App, _step: Duration) -> Result<()> {
+        Ok(())
+    }
+
+    /// Draws a frame into the window's back buffer; the runner presents it afterwards.
+    ///
+    /// `alpha` is how far the current time lies between the last two fixed updates, in
+    /// `[0, 1)`, for interpolating the simulation state.
+    fn render(&mut self, window: &mut Window, alpha: f32) -> Result<()>;
+
+    /// Called for every window event, after the runner's own handling.
+    fn on_event(&mut self, _window: &mut Window, _event: &WindowEvent) -> Result<()> {
+        Ok(())
+    }
+
+    /// The window received `WindowEvent::CloseRequested`, after `on_event`. Returning
+    /// `false` vetoes the close, e.g. to ask about unsaved changes first.
+    fn on_close_requested(&mut self, _window: &mut Window) -> Result<bool> {
+        Ok(true)
+    }
+
+    /// The window's client area and swap chain were resized, in physical pixels.
+    fn on_resize(&mut self, _window: &mut Window, _width: i32, _height: i32) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called before the runner returns, while the remaining windows still exist.
+    fn shutdown(&mut self, _app: &mut App) {}
+}
@@ -0,0 +1,447 @@
+use std::ptr;
+
+use windows::core::Error;
+use windows::Win32::Foundation::{HANDLE, HWND};
+use windows::Win32::System::DataExchange::{
+    CloseClipboard, EmptyClipboard, GetClipboardData, IsClipboardFormatAvailable, OpenClipboard,
+    SetClipboardData,
+};
+use windows::Win32::System::Memory::{
+    GlobalAlloc, GlobalFree, GlobalLock, GlobalSize, GlobalUnlock, GMEM_MOVEABLE,
+};
+use windows::Win32::System::SystemServices::{CF_DIB, CF_UNICODETEXT};
+
+use crate::error::Win32Error;
+pub type Result<T> = core::result::Result<T, Win32Error>;
+
+/// Size of a BITMAPINFOHEADER, the only DIB header version written by `encode_dib`.
+const BITMAPINFOHEADER_SIZE: usize = 40;
+/// Size of a BITMAPV3INFOHEADER, the first header version with an alpha mask.
+const BITMAPV3INFOHEADER_SIZE: usize = 56;
+const BI_RGB: u32 = 0;
+const BI_BITFIELDS: u32 = 3;
+
+/// Image with 8 bit RGBA pixels in rows from top to bottom, without padding.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Image {
+    width: u32,
+    height: u32,
+    rgba: Vec<u8>,
+}
+
+impl Image {
+    /// Returns `None` if `rgba` does not hold exactly `width * height` pixels, or if the
+    /// image is too large for a DIB.
+    pub fn new(width: u32, height: u32, rgba: Vec<u8>) -> Option<Image> {
+        let size = (width as u64) * (height as u64) * 4;
+        if width > i32::MAX as u32
+            || height > i32::MAX as u32
+            || size > u32::MAX as u64
+            || rgba.len() as u64 != size
+        {
+            return None;
+        }
+        Some(Image {
+            width,
+            height,
+            rgba,
+        })
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn rgba(&self) -> &[u8] {
+        &self.rgba
+    }
+
+    pub fn into_rgba(self) -> Vec<u8> {
+        self.rgba
+    }
+}
+
+/// Converts text to nul-terminated UTF-16 with the CRLF line endings the clipboard uses.
+pub fn encode_text(text: &str) -> Vec<u16> {
+    let mut wide = Vec::with_capacity(text.len() + 1);
+    let mut previous = 0;
+    for unit in text.encode_utf16() {
+        if unit == b'\n' as u16 && previous != b'\r' as u16 {
+            wide.push(b'\r' as u16);
+        }
+        wide.push(unit);
+        previous = unit;
+    }
+    wide.push(0);
+    wide
+}
+
+/// Converts clipboard UTF-16 up to the first nul to a `String` with LF line endings.
+/// Unpaired surrogates are replaced with U+FFFD.
+pub fn decode_text(wide: &[u16]) -> String {
+    let end = wide.iter().position(|unit| *unit == 0).unwrap_or(wide.len());
+    String::from_utf16_lossy(&wide[..end]).replace("\r\n", "\n")
+}
+
+/// Encodes the image as a packed 32 bit bottom-up DIB, the layout of CF_DIB data.
+///
+/// `Image::new` made sure the sizes fit the header fields.
+pub fn encode_dib(image: &Image) -> Vec<u8> {
+    let pixel_bytes = image.rgba.len();
+    let mut dib = Vec::with_capacity(BITMAPINFOHEADER_SIZE + pixel_bytes);
+    dib.extend_from_slice(&(BITMAPINFOHEADER_SIZE as u32).to_le_bytes());
+    dib.extend_from_slice(&(image.width as i32).to_le_bytes());
+    dib.extend_from_slice(&(image.height as i32).to_le_bytes());
+    dib.extend_from_slice(&1u16.to_le_bytes()); // planes
+    dib.extend_from_slice(&32u16.to_le_bytes()); // bits per pixel
+    dib.extend_from_slice(&BI_RGB.to_le_bytes());
+    dib.extend_from_slice(&(pixel_bytes as u32).to_le_bytes());
+    dib.extend_from_slice(&[0; 16]); // resolution and palette sizes
+
+    let row_bytes = image.width as usize * 4;
+    if row_bytes > 0 {
+        for row in image.rgba.chunks_exact(row_bytes).rev() {
+            for pixel in row.chunks_exact(4) {
+                dib.extend_from_slice(&[pixel[2], pixel[1], pixel[0], pixel[3]]);
+            }
+        }
+    }
+    dib
+}
+
+/// Decodes uncompressed 24 or 32 bit DIB data, and 16 or 32 bit DIB data with
+/// BI_BITFIELDS color masks. Other formats yield `None`.
+///
+/// Most applications leave the fourth byte of 32 bit DIBs zero, so an image without
+/// any non-zero alpha is taken to be opaque.
+pub fn decode_dib(dib: &[u8]) -> Option<Image> {
+    let u16_at = |offset: usize| -> Option<u16> {
+        Some(u16::from_le_bytes(dib.get(offset..offset + 2)?.try_into().ok()?))
+    };
+    let u32_at = |offset: usize| -> Option<u32> {
+        Some(u32::from_le_bytes(dib.get(offset..offset + 4)?.try_into().ok()?))
+    };
+
+    let header_size = u32_at(0)? as usize;
+    if header_size < BITMAPINFOHEADER_SIZE {
+        return None;
+    }
+    let width = u32_at(4)? as i32;
+    let height = u32_at(8)? as i32;
+    let bit_count = u16_at(14)?;
+    let compression = u32_at(16)?;
+    let colors_used = u32_at(32)? as usize;
+    if width <= 0 || height == 0 {
+        return None;
+    }
+
+    let bytes_per_pixel = match (bit_count, compression) {
+        (24, BI_RGB) => 3,
+        (32, BI_RGB) | (32, BI_BITFIELDS) => 4,
+        (16, BI_BITFIELDS) => 2,
+        _ => return None,
+    };
+    // Red, green, blue and alpha. The color masks directly follow a plain
+    // BITMAPINFOHEADER and are part of later header versions, so they are read from the
+    // same offsets
+    let masks = if compression == BI_BITFIELDS {
+        let alpha = if header_size >= BITMAPV3INFOHEADER_SIZE {
+            u32_at(52)?
+        } else {
+            0
+        };
+        [u32_at(40)?, u32_at(44)?, u32_at(48)?, alpha]
+    } else if bytes_per_pixel == 4 {
+        [0x00FF_0000, 0x0000_FF00, 0x0000_00FF, 0xFF00_0000]
+    } else {
+        [0x00FF_0000, 0x0000_FF00, 0x0000_00FF, 0]
+    };
+    if !masks.iter().all(|mask| is_contiguous(*mask)) {
+        return None;
+    }
+    // A plain BITMAPINFOHEADER is followed by the three color masks for BI_BITFIELDS
+    let mut offset = header_size + colors_used * 4;
+    if compression == BI_BITFIELDS && header_size == BITMAPINFOHEADER_SIZE {
+        offset += 12;
+    }
+
+    let width = width as usize;
+    let rows = height.unsigned_abs() as usize;
+    let bottom_up = height > 0;
+    // Rows are padded to a multiple of four bytes
+    let stride = (width * bytes_per_pixel + 3) & !3;
+    let pixels = dib.get(offset..offset.checked_add(stride.checked_mul(rows)?)?)?;
+
+    let mut rgba = Vec::with_capacity(width * rows * 4);
+    let mut any_alpha = false;
+    for y in 0..rows {
+        let row = if bottom_up { rows - 1 - y } else { y };
+        let row = &pixels[row * stride..row * stride + width * bytes_per_pixel];
+        for pixel in row.chunks_exact(bytes_per_pixel) {
+            let value = pixel
+                .iter()
+                .rev()
+                .fold(0, |value, byte| value << 8 | *byte as u32);
+            let channels = masks.map(|mask| channel(value, mask));
+            any_alpha |= channels[3] != 0;
+            rgba.extend_from_slice(&channels);
+        }
+    }
+    if masks[3] == 0 || !any_alpha {
+        rgba.chunks_exact_mut(4).for_each(|pixel| pixel[3] = 255);
+    }
+
+    Some(Image {
+        width: width as u32,
+        height: rows as u32,
+        rgba,
+    })
+}
+
+/// Whether the set bits of `mask` are adjacent, as DIB color masks must be.
+fn is_contiguous(mask: u32) -> bool {
+    let bits = mask >> mask.trailing_zeros().min(31);
+    bits & bits.wrapping_add(1) == 0
+}
+
+/// Channel selected by `mask` from a pixel, scaled to 8 bits. 0 for an empty mask.
+fn channel(pixel: u32, mask: u32) -> u8 {
+    if mask == 0 {
+        return 0;
+    }
+    let bits = mask.count_ones();
+    let value = (pixel & mask) >> mask.trailing_zeros();
+    if bits >= 8 {
+        (value >> (bits - 8)) as u8
+    } else {
+        (value * 255 / ((1 << bits) - 1)) as u8
+    }
+}
+
+/// Keeps the clipboard open for `owner` until dropped.
+struct OpenClipboardGuard;
+
+impl OpenClipboardGuard {
+    fn open(owner: HWND) -> Result<OpenClipboardGuard> {
+        unsafe {
+            // Fails while another window has the clipboard open
+            OpenClipboard(owner).ok().map_err(|e| win_error!(e))?;
+        }
+        Ok(OpenClipboardGuard)
+    }
+}
+
+impl Drop for OpenClipboardGuard {
+    fn drop(&mut self) {
+        unsafe {
+            CloseClipboard();
+        }
+    }
+}
+
+pub(crate) fn get_text(owner: HWND) -> Result<Option<String>> {
+    let data = get_data(owner, CF_UNICODETEXT)?;
+    Ok(data.map(|bytes| {
+        let wide: Vec<u16> = bytes
+            .chunks_exact(2)
+            .map(|unit| u16::from_le_bytes([unit[0], unit[1]]))
+            .collect();
+        decode_text(&wide)
+    }))
+}
+
+pub(crate) fn set_text(owner: HWND, text: &str) -> Result<()> {
+    let bytes: Vec<u8> = encode_text(text)
+        .iter()
+        .flat_map(|unit| unit.to_le_bytes())
+        .collect();
+    set_data(owner, CF_UNICODETEXT, &bytes)
+}
+
+pub(crate) fn get_image(owner: HWND) -> Result<Option<Image>> {
+    Ok(get_data(owner, CF_DIB)?.and_then(|dib| decode_dib(&dib)))
+}
+
+pub(crate) fn set_image(owner: HWND, image: &Image) -> Result<()> {
+    set_data(owner, CF_DIB, &encode_dib(image))
+}
+
+/// Copies the clipboard data in `format`, `None` if there is none.
+fn get_data(owner: HWND, format: u32) -> Result<Option<Vec<u8>>> {
+    unsafe {
+        if !IsClipboardFormatAvailable(format).as_bool() {
+            return Ok(None);
+        }
+        let _clipboard = OpenClipboardGuard::open(owner)?;
+        let handle = GetClipboardData(format);
+        if handle.is_invalid() {
+            return Err(win_error!(Error::from_win32()));
+        }
+        // The clipboard keeps ownership of the memory, it is only locked while copying
+        let memory = GlobalLock(handle.0);
+        if memory.is_null() {
+            return Err(win_error!(Error::from_win32()));
+        }
+        let size = GlobalSize(handle.0);
+        let data = std::slice::from_raw_parts(memory as *const u8, size).to_vec();
+        GlobalUnlock(handle.0);
+        Ok(Some(data))
+    }
+}
+
+/// Replaces the clipboard content with `data` in `format`.
+fn set_data(owner: HWND, format: u32, data: &[u8]) -> Result<()> {
+    unsafe {
+        let memory = GlobalAlloc(GMEM_MOVEABLE, data.len());
+        if memory == 0 {
+            return Err(win_error!(Error::from_win32()));
+        }
+        let pointer = GlobalLock(memory);
+        if pointer.is_null() {
+            let error = Error::from_win32();
+            GlobalFree(memory);
+            return Err(win_error!(error));
+        }
+        ptr::copy_nonoverlapping(data.as_ptr(), pointer as *mut u8, data.len());
+        GlobalUnlock(memory);
+
+        let result = OpenClipboardGuard::open(owner).and_then(|_clipboard| {
+            EmptyClipboard().ok().map_err(|e| win_error!(e))?;
+            if SetClipboardData(format, HANDLE(memory)).is_invalid() {
+                return Err(win_error!(Error::from_win32()));
+            }
+            Ok(())
+        });
+        // On success the clipboard owns the memory
+        if result.is_err() {
+            GlobalFree(memory);
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dib_header(width: i32, height: i32, bit_count: u16, compression: u32) -> Vec<u8> {
+        let mut header = Vec::new();
+        header.extend_from_slice(&(BITMAPINFOHEADER_SIZE as u32).to_le_bytes());
+        header.extend_from_slice(&width.to_le_bytes());
+        header.extend_from_slice(&height.to_le_bytes());
+        header.extend_from_slice(&1u16.to_le_bytes());
+        header.extend_from_slice(&bit_count.to_le_bytes());
+        header.extend_from_slice(&compression.to_le_bytes());
+        header.extend_from_slice(&[0; 20]);
+        header
+    }
+
+    fn wide(text: &str) -> Vec<u16> {
+        text.encode_utf16().collect()
+    }
+
+    #[test]
+    fn text_uses_crlf_on_the_clipboard() {
+        assert_eq!(encode_text("a\nb\r\nc"), wide("a\r\nb\r\nc\0"));
+        assert_eq!(decode_text(&wide("a\r\nb\nc\0")), "a\nb\nc");
+    }
+
+    #[test]
+    fn text_round_trips() {
+        let text = "line\nnext line \u{1F600}\n";
+        assert_eq!(decode_text(&encode_text(text)), text);
+        assert_eq!(decode_text(&encode_text("")), "");
+    }
+
+    #[test]
+    fn decode_text_stops_at_the_first_nul_or_the_end() {
+        assert_eq!(decode_text(&wide("first\0second\0")), "first");
+        assert_eq!(decode_text(&wide("no terminator")), "no terminator");
+        assert_eq!(decode_text(&[0xD800, b'x' as u16]), "\u{FFFD}x");
+    }
+
+    #[test]
+    fn image_new_checks_the_pixel_count() {
+        assert!(Image::new(2, 1, vec![0; 8]).is_some());
+        assert!(Image::new(2, 1, vec![0; 7]).is_none());
+        assert!(Image::new(u32::MAX, 1, Vec::new()).is_none());
+        assert!(Image::new(0x8000, 0x8000, Vec::new()).is_none());
+    }
+
+    #[test]
+    fn dib_round_trips() {
+        let rgba = vec![
+            1, 2, 3, 255, 4, 5, 6, 128, 7, 8, 9, 0, //
+            10, 11, 12, 64, 13, 14, 15, 255, 16, 17, 18, 1,
+        ];
+        let image = Image::new(3, 2, rgba).unwrap();
+        let dib = encode_dib(&image);
+        assert_eq!(dib.len(), BITMAPINFOHEADER_SIZE + 24);
+        // Bottom-up rows of BGRA pixels
+        assert_eq!(
+            dib[BITMAPINFOHEADER_SIZE..BITMAPINFOHEADER_SIZE + 4],
+            [12, 11, 10, 64]
+        );
+        assert_eq!(decode_dib(&dib), Some(image));
+    }
+
+    #[test]
+    fn dib_without_alpha_is_opaque() {
+        let image = Image::new(1, 1, vec![1, 2, 3, 0]).unwrap();
+        let decoded = decode_dib(&encode_dib(&image)).unwrap();
+        assert_eq!(decoded.rgba(), [1, 2, 3, 255]);
+    }
+
+    #[test]
+    fn decode_dib_reads_padded_24_bit_rows() {
+        // Top-down, one pixel per row padded to four bytes
+        let mut dib = dib_header(1, -2, 24, BI_RGB);
+        dib.extend_from_slice(&[3, 2, 1, 0, 6, 5, 4, 0]);
+        let image = decode_dib(&dib).unwrap();
+        assert_eq!((image.width(), image.height()), (1, 2));
+        assert_eq!(image.rgba(), [1, 2, 3, 255, 4, 5, 6, 255]);
+    }
+
+    #[test]
+    fn decode_dib_applies_bitfield_masks() {
+        // 32 bit with the bytes in RGBA order, the masks after the header
+        let mut dib = dib_header(1, 1, 32, BI_BITFIELDS);
+        for mask in [0x0000_00FFu32, 0x0000_FF00, 0x00FF_0000] {
+            dib.extend_from_slice(&mask.to_le_bytes());
+        }
+        dib.extend_from_slice(&[1, 2, 3, 0]);
+        assert_eq!(decode_dib(&dib).unwrap().rgba(), [1, 2, 3, 255]);
+
+        // 16 bit 5-6-5
+        let mut dib = dib_header(2, 1, 16, BI_BITFIELDS);
+        for mask in [0xF800u32, 0x07E0, 0x001F] {
+            dib.extend_from_slice(&mask.to_le_bytes());
+        }
+        dib.extend_from_slice(&0xF800u16.to_le_bytes());
+        dib.extend_from_slice(&0x0410u16.to_le_bytes());
+        assert_eq!(
+            decode_dib(&dib).unwrap().rgba(),
+            [255, 0, 0, 255, 0, 129, 131, 255]
+        );
+    }
+
+    #[test]
+    fn decode_dib_rejects_invalid_data() {
+        let mut dib = dib_header(2, 2, 32, BI_RGB);
+        dib.extend_from_slice(&[0; 12]);
+        assert_eq!(decode_dib(&dib), None);
+        assert_eq!(decode_dib(&dib[..20]), None);
+        assert_eq!(decode_dib(&dib_header(1, 1, 8, BI_RGB)), None);
+
+        let mut dib = dib_header(1, 1, 32, BI_BITFIELDS);
+        for mask in [0x00F0_0F00u32, 0x0000_00FF, 0xFF00_0000] {
+            dib.extend_from_slice(&mask.to_le_bytes());
+        }
+        dib.extend_from_slice(&[0; 4]);
+        assert_eq!(decode_dib(&dib), None);
+    }
+}
@@ -0,0 +1,501 @@
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+
+use windows::core::Error;
+use windows::Win32::Foundation::{E_FAIL, E_UNEXPECTED, PWSTR};
+use windows::Win32::UI::Controls::Dialogs::{
+    CommDlgExtendedError, GetOpenFileNameW, GetSaveFileNameW, OFN_EXPLORER, OFN_FILEMUSTEXIST,
+    OFN_NOCHANGEDIR, OFN_OVERWRITEPROMPT, OFN_PATHMUSTEXIST, OPENFILENAMEW,
+};
+use windows::Win32::UI::WindowsAndMessaging::{
+    MessageBoxW, IDABORT, IDCANCEL, IDIGNORE, IDNO, IDOK, IDRETRY, IDYES, MB_ABORTRETRYIGNORE,
+    MB_ICONERROR, MB_ICONINFORMATION, MB_ICONQUESTION, MB_ICONWARNING, MB_OK, MB_OKCANCEL,
+    MB_RETRYCANCEL, MB_YESNO, MB_YESNOCANCEL,
+};
+
+use crate::error::Win32Error;
+use crate::wide_string::WideCString;
+use crate::win32_common::os_string_from_wide;
+use crate::window::WindowId;
+pub type Result<T> = core::result::Result<T, Win32Error>;
+
+/// Longest path, in UTF-16 code units, a file dialog can return.
+const MAX_FILE_PATH: usize = 32 * 1024;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MessageBoxButtons {
+    Ok,
+    OkCancel,
+    YesNo,
+    YesNoCancel,
+    RetryCancel,
+    AbortRetryIgnore,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MessageBoxIcon {
+    None,
+    Information,
+    Warning,
+    Error,
+    Question,
+}
+
+/// Button the user chose; closing the box counts as `Cancel` where there is one.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MessageBoxResult {
+    Ok,
+    Cancel,
+    Yes,
+    No,
+    Retry,
+    Abort,
+    Ignore,
+}
+
+/// Named group of file extensions shown in a file dialog, e.g. `Images` with `png` and
+/// `jpg`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct FileFilter {
+    pub name: String,
+    /// Extensions without the leading dot; `*` matches every file.
+    pub extensions: Vec<String>,
+}
+
+impl FileFilter {
+    pub fn new(name: &str, extensions: &[&str]) -> FileFilter {
+        FileFilter {
+            name: name.into(),
+            extensions: extensions
+                .iter()
+                .map(|extension| extension.to_string())
+                .collect(),
+        }
+    }
+}
+
+/// Configuration of an open or save file dialog.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct FileDialog {
+    pub(crate) owner: Option<WindowId>,
+    pub(crate) title: Option<String>,
+    pub(crate) filters: Vec<FileFilter>,
+    pub(crate) directory: Option<PathBuf>,
+    pub(crate) file_name: Option<String>,
+    pub(crate) default_extension: Option<String>,
+}
+
+impl FileDialog {
+    pub fn new() -> FileDialog {
+        FileDialog::default()
+    }
+
+    /// The dialog is modal to the owner window.
+    pub fn owner(mut self, owner: WindowId) -> FileDialog {
+        self.owner = Some(owner);
+        self
+    }
+
+    pub fn title(mut self, title: &str) -> FileDialog {
+        self.title = Some(title.into());
+        self
+    }
+
+    /// Filters are offered in the order they are added, the first one preselected.
+    pub fn filter(mut self, name: &str, extensions: &[&str]) -> FileDialog {
+        self.filters.push(FileFilter::new(name, extensions));
+        self
+    }
+
+    pub fn directory(mut self, directory: &Path) -> FileDialog {
+        self.directory = Some(directory.into());
+        self
+    }
+
+    /// Preset file name.
+    pub fn file_name(mut self, file_name: &str) -> FileDialog {
+        self.file_name = Some(file_name.into());
+        self
+    }
+
+    /// Appended to names the user types without an extension when saving.
+    pub fn default_extension(mut self, extension: &str) -> FileDialog {
+        self.default_extension = Some(extension.into());
+        self
+    }
+
+    pub fn title_text(&self) -> Option<&str> {
+        self.title.as_deref()
+    }
+
+    pub fn filters(&self) -> &[FileFilter] {
+        &self.filters
+    }
+
+    /// The filter list in the format of `OPENFILENAMEW::lpstrFilter`: pairs of name and
+    /// `;`-separated patterns, each nul-terminated, with a final extra nul.
+    pub fn filter_spec(&self) -> Vec<u16> {
+        let mut spec = String::new();
+        for filter in &self.filters {
+            let patterns: Vec<String> = filter
+                .extensions
+                .iter()
+                .map(|extension| format!("*.{}", extension).replace("*.*", "*"))
+                .collect();
+            spec += &format!("{}\0{}\0", filter.name, patterns.join(";"));
+        }
+        let mut spec: Vec<u16> = spec.encode_utf16().collect();
+        spec.push(0);
+        spec
+    }
+}
+
+/// Dialog requests as recorded by `ScriptedDialogs`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum DialogRequest {
+    MessageBox {
+        title: String,
+        text: String,
+        buttons: MessageBoxButtons,
+        icon: MessageBoxIcon,
+    },
+    OpenFile(FileDialog),
+    SaveFile(FileDialog),
+}
+
+/// Modal dialogs an application can show.
+///
+/// Code that asks the user goes through this trait so headless builds and tests can
+/// use `ScriptedDialogs` in place of `NativeDialogs`.
+pub trait Dialogs {
+    fn message_box(
+        &mut self,
+        owner: Option<WindowId>,
+        title: &str,
+        text: &str,
+        buttons: MessageBoxButtons,
+        icon: MessageBoxIcon,
+    ) -> Result<MessageBoxResult>;
+
+    /// Asks for an existing file; `None` if the user cancelled.
+    fn open_file(&mut self, dialog: &FileDialog) -> Result<Option<PathBuf>>;
+
+    /// Asks for a file to write, confirming before overwriting; `None` if the user
+    /// cancelled.
+    fn save_file(&mut self, dialog: &FileDialog) -> Result<Option<PathBuf>>;
+}
+
+/// Dialogs shown by the OS.
+#[derive(Default)]
+pub struct NativeDialogs;
+
+impl NativeDialogs {
+    pub fn new() -> NativeDialogs {
+        NativeDialogs
+    }
+
+    fn file_dialog(&self, dialog: &FileDialog, save: bool) -> Result<Option<PathBuf>> {
+        let filter_spec = dialog.filter_spec();
+        let title = optional_wide(dialog.title.as_deref())?;
+        let directory = dialog
+            .directory
+            .as_deref()
+            .map(WideCString::try_from)
+            .transpose()?;
+        let default_extension = optional_wide(dialog.default_extension.as_deref())?;
+
+        let mut file = vec![0u16; MAX_FILE_PATH];
+        if let Some(file_name) = &dialog.file_name {
+            let file_name: Vec<u16> = file_name.encode_utf16().collect();
+            let length = file_name.len().min(MAX_FILE_PATH - 1);
+            file[..length].copy_from_slice(&file_name[..length]);
+        }
+
+        let as_pwstr = |text: &Option<WideCString>| match text {
+            Some(text) => text.as_pcwstr().as_pwstr(),
+            None => PWSTR::default(),
+        };
+        let mut options = OPENFILENAMEW {
+            lStructSize: std::mem::size_of::<OPENFILENAMEW>() as u32,
+            hwndOwner: dialog.owner.map_or(0, |owner| owner.handle()),
+            lpstrFilter: if dialog.filters.is_empty() {
+                PWSTR::default()
+            } else {
+                PWSTR(filter_spec.as_ptr() as *mut u16)
+            },
+            nFilterIndex: 1,
+            lpstrFile: PWSTR(file.as_mut_ptr()),
+            nMaxFile: file.len() as u32,
+            lpstrInitialDir: as_pwstr(&directory),
+            lpstrTitle: as_pwstr(&title),
+            lpstrDefExt: as_pwstr(&default_extension),
+            Flags: OFN_EXPLORER | OFN_NOCHANGEDIR | OFN_PATHMUSTEXIST,
+            ..Default::default()
+        };
+        options.Flags |= if save {
+            OFN_OVERWRITEPROMPT
+        } else {
+            OFN_FILEMUSTEXIST
+        };
+
+        unsafe {
+            let chosen = if save {
+                GetSaveFileNameW(&mut options)
+            } else {
+                GetOpenFileNameW(&mut options)
+            };
+            if !chosen.as_bool() {
+                // Zero means the user cancelled, anything else is a dialog error code
+                let code = CommDlgExtendedError();
+                if code == 0 {
+                    return Ok(None);
+                }
+                let message = format!("common dialog error {:#x}", code);
+                return Err(Win32Error::with_message(E_FAIL, message));
+            }
+        }
+        let length = file
+            .iter()
+            .position(|unit| *unit == 0)
+            .unwrap_or(file.len());
+        Ok(Some(PathBuf::from(os_string_from_wide(&file[..length]))))
+    }
+}
+
+impl Dialogs for NativeDialogs {
+    fn message_box(
+        &mut self,
+        owner: Option<WindowId>,
+        title: &str,
+        text: &str,
+        buttons: MessageBoxButtons,
+        icon: MessageBoxIcon,
+    ) -> Result<MessageBoxResult> {
+        let style = match buttons {
+            MessageBoxButtons::Ok => MB_OK,
+            MessageBoxButtons::OkCancel => MB_OKCANCEL,
+            MessageBoxButtons::YesNo => MB_YESNO,
+            MessageBoxButtons::YesNoCancel => MB_YESNOCANCEL,
+            MessageBoxButtons::RetryCancel => MB_RETRYCANCEL,
+            MessageBoxButtons::AbortRetryIgnore => MB_ABORTRETRYIGNORE,
+        } | match icon {
+            MessageBoxIcon::None => 0,
+            MessageBoxIcon::Information => MB_ICONINFORMATION,
+            MessageBoxIcon::Warning => MB_ICONWARNING,
+            MessageBoxIcon::Error => MB_ICONERROR,
+            MessageBoxIcon::Question => MB_ICONQUESTION,
+        };
+        let title = WideCString::try_from(title)?;
+        let text = WideCString::try_from(text)?;
+        let result = unsafe {
+            MessageBoxW(
+                owner.map_or(0, |owner| owner.handle()),
+                &text,
+                &title,
+                style,
+            )
+        };
+        match result {
+            IDOK => Ok(MessageBoxResult::Ok),
+            IDCANCEL => Ok(MessageBoxResult::Cancel),
+            IDYES => Ok(MessageBoxResult::Yes),
+            IDNO => Ok(MessageBoxResult::No),
+            IDRETRY => Ok(MessageBoxResult::Retry),
+            IDABORT => Ok(MessageBoxResult::Abort),
+            IDIGNORE => Ok(MessageBoxResult::Ignore),
+            _ => Err(win_error!(Error::from_win32())),
+        }
+    }
+
+    fn open_file(&mut self, dialog: &FileDialog) -> Result<Option<PathBuf>> {
+        self.file_dialog(dialog, false)
+    }
+
+    fn save_file(&mut self, dialog: &FileDialog) -> Result<Option<PathBuf>> {
+        self.file_dialog(dialog, true)
+    }
+}
+
+enum ScriptedAnswer {
+    MessageBox(MessageBoxResult),
+    File(Option<PathBuf>),
+}
+
+/// Answers dialogs from a script instead of asking a user, and records what was asked.
+///
+/// Answers are consumed in order. Asking a dialog when the next answer is missing or of
+/// the wrong kind is an error, so tests notice unexpected dialogs; an answer of the
+/// wrong kind stays queued for the dialog it was meant for.
+#[derive(Default)]
+pub struct ScriptedDialogs {
+    answers: VecDeque<ScriptedAnswer>,
+    requests: Vec<DialogRequest>,
+}
+
+impl ScriptedDialogs {
+    pub fn new() -> ScriptedDialogs {
+        ScriptedDialogs::default()
+    }
+
+    pub fn answer_message_box(&mut self, result: MessageBoxResult) -> &mut ScriptedDialogs {
+        self.answers.push_back(ScriptedAnswer::MessageBox(result));
+        self
+    }
+
+    /// Answer to the next open or save dialog; `None` cancels it.
+    pub fn answer_file(&mut self, path: Option<PathBuf>) -> &mut ScriptedDialogs {
+        self.answers.push_back(ScriptedAnswer::File(path));
+        self
+    }
+
+    /// Dialogs asked so far, oldest first.
+    pub fn requests(&self) -> &[DialogRequest] {
+        &self.requests
+    }
+
+    /// Number of answers not yet consumed.
+    pub fn pending_answers(&self) -> usize {
+        self.answers.len()
+    }
+
+    fn next_file_answer(&mut self, request: DialogRequest) -> Result<Option<PathBuf>> {
+        self.requests.push(request);
+        match self.answers.front_mut() {
+            Some(ScriptedAnswer::File(path)) => {
+                let path = path.take();
+                self.answers.pop_front();
+                Ok(path)
+            }
+            _ => Err(unexpected_dialog()),
+        }
+    }
+}
+
+impl Dialogs for ScriptedDialogs {
+    fn message_box(
+        &mut self,
+        _owner: Option<WindowId>,
+        title: &str,
+        text: &str,
+        buttons: MessageBoxButtons,
+        icon: MessageBoxIcon,
+    ) -> Result<MessageBoxResult> {
+        self.requests.push(DialogRequest::MessageBox {
+            title: title.into(),
+            text: text.into(),
+            buttons,
+            icon,
+        });
+        match self.answers.front() {
+            Some(ScriptedAnswer::MessageBox(result)) => {
+                let result = *result;
+                self.answers.pop_front();
+                Ok(result)
+            }
+            _ => Err(unexpected_dialog()),
+        }
+    }
+
+    fn open_file(&mut self, dialog: &FileDialog) -> Result<Option<PathBuf>> {
+        self.next_file_answer(DialogRequest::OpenFile(dialog.clone()))
+    }
+
+    fn save_file(&mut self, dialog: &FileDialog) -> Result<Option<PathBuf>> {
+        self.next_file_answer(DialogRequest::SaveFile(dialog.clone()))
+    }
+}
+
+fn optional_wide(text: Option<&str>) -> Result<Option<WideCString>> {
+    Ok(text.map(WideCString::try_from).transpose()?)
+}
+
+fn unexpected_dialog() -> Win32Error {
+    Win32Error::with_message(E_UNEXPECTED, "no scripted answer for this dialog")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ask_message_box(dialogs: &mut ScriptedDialogs) -> Result<MessageBoxResult> {
+        dialogs.message_box(
+            None,
+            "Quit",
+            "Save changes?",
+            MessageBoxButtons::YesNoCancel,
+            MessageBoxIcon::Question,
+        )
+    }
+
+    #[test]
+    fn answers_are_consumed_in_order() {
+        let mut dialogs = ScriptedDialogs::new();
+        dialogs
+            .answer_message_box(MessageBoxResult::Yes)
+            .answer_file(Some(PathBuf::from("scene.txt")))
+            .answer_file(None);
+
+        assert_eq!(
+            ask_message_box(&mut dialogs).ok(),
+            Some(MessageBoxResult::Yes)
+        );
+        let save = FileDialog::new().title("Save scene");
+        assert_eq!(
+            dialogs.save_file(&save).ok(),
+            Some(Some(PathBuf::from("scene.txt")))
+        );
+        assert_eq!(dialogs.open_file(&FileDialog::new()).ok(), Some(None));
+        assert_eq!(dialogs.pending_answers(), 0);
+
+        assert_eq!(dialogs.requests().len(), 3);
+        assert_eq!(
+            dialogs.requests()[0],
+            DialogRequest::MessageBox {
+                title: "Quit".into(),
+                text: "Save changes?".into(),
+                buttons: MessageBoxButtons::YesNoCancel,
+                icon: MessageBoxIcon::Question,
+            }
+        );
+        assert_eq!(dialogs.requests()[1], DialogRequest::SaveFile(save));
+    }
+
+    #[test]
+    fn mismatched_answers_stay_queued() {
+        let mut dialogs = ScriptedDialogs::new();
+        dialogs.answer_file(Some(PathBuf::from("scene.txt")));
+
+        let error = ask_message_box(&mut dialogs).err().map(|e| e.code());
+        assert_eq!(error, Some(E_UNEXPECTED));
+        assert_eq!(dialogs.pending_answers(), 1);
+        assert_eq!(
+            dialogs.open_file(&FileDialog::new()).ok(),
+            Some(Some(PathBuf::from("scene.txt")))
+        );
+
+        dialogs.answer_message_box(MessageBoxResult::Ok);
+        let error = dialogs
+            .save_file(&FileDialog::new())
+            .err()
+            .map(|e| e.code());
+        assert_eq!(error, Some(E_UNEXPECTED));
+        assert_eq!(
+            ask_message_box(&mut dialogs).ok(),
+            Some(MessageBoxResult::Ok)
+        );
+    }
+
+    #[test]
+    fn exhausted_scripts_fail() {
+        let mut dialogs = ScriptedDialogs::new();
+        let error = ask_message_box(&mut dialogs).err().map(|e| e.code());
+        assert_eq!(error, Some(E_UNEXPECTED));
+        let error = dialogs
+            .open_file(&FileDialog::new())
+            .err()
+            .map(|e| e.code());
+        assert_eq!(error, Some(E_UNEXPECTED));
+        // Unanswered dialogs are still recorded
+        assert_eq!(dialogs.requests().len(), 2);
+    }
+}
@@ -0,0 +1,48 @@
+/// Dots per inch of a display at 100% scaling.
+pub const DEFAULT_DPI: u32 = 96;
+
+/// Ratio between physical pixels and logical units for a display with the given DPI.
+pub fn scale_factor_for_dpi(dpi: u32) -> f32 {
+    dpi as f32 / DEFAULT_DPI as f32
+}
+
+/// Size in device pixels, as used by the OS and the swap chain.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct PhysicalSize {
+    pub width: i32,
+    pub height: i32,
+}
+
+/// Size in DPI-independent units: one unit is one pixel at 100% scaling.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct LogicalSize {
+    pub width: f32,
+    pub height: f32,
+}
+
+impl PhysicalSize {
+    pub fn new(width: i32, height: i32) -> PhysicalSize {
+        PhysicalSize { width, height }
+    }
+
+    pub fn to_logical(self, scale_factor: f32) -> LogicalSize {
+        LogicalSize {
+            width: self.width as f32 / scale_factor,
+            height: self.height as f32 / scale_factor,
+        }
+    }
+}
+
+impl LogicalSize {
+    pub fn new(width: f32, height: f32) -> LogicalSize {
+        LogicalSize { width, height }
+    }
+
+    /// Rounds to the nearest whole pixel.
+    pub fn to_physical(self, scale_factor: f32) -> PhysicalSize {
+        PhysicalSize {
+            width: (self.width * scale_factor).round() as i32,
+            height: (self.height * scale_factor).round() as i32,
+        }
+    }
+}
@@ -0,0 +1,316 @@
+use std::cell::Cell;
+use std::mem::{self, ManuallyDrop};
+use std::path::PathBuf;
+use std::ptr;
+use std::sync::atomic::{fence, AtomicU32, Ordering};
+
+use windows::core::{IUnknown, Interface, RawPtr, GUID, HRESULT};
+use windows::Win32::Foundation::{E_NOINTERFACE, HWND, POINT, POINTL, PWSTR, S_OK};
+use windows::Win32::Graphics::Gdi::ScreenToClient;
+use windows::Win32::System::Com::{IDataObject, DVASPECT_CONTENT, FORMATETC, TYMED_HGLOBAL};
+use windows::Win32::System::Ole::{
+    IDropTarget, OleInitialize, OleUninitialize, RegisterDragDrop, ReleaseStgMedium,
+    RevokeDragDrop, DROPEFFECT_COPY, DROPEFFECT_NONE,
+};
+use windows::Win32::System::SystemServices::CF_HDROP;
+use windows::Win32::UI::Shell::DragQueryFileW;
+
+use crate::error::Win32Error;
+use crate::event::WindowEvent;
+use crate::win32_common::os_string_from_wide;
+use crate::window::{self, WindowState};
+pub type Result<T> = core::result::Result<T, Win32Error>;
+
+/// Layout of the IDropTarget vtable, IUnknown methods first.
+#[repr(C)]
+struct DropTargetVtbl {
+    query_interface: unsafe extern "system" fn(RawPtr, *const GUID, *mut RawPtr) -> HRESULT,
+    add_ref: unsafe extern "system" fn(RawPtr) -> u32,
+    release: unsafe extern "system" fn(RawPtr) -> u32,
+    drag_enter: unsafe extern "system" fn(RawPtr, RawPtr, u32, POINTL, *mut u32) -> HRESULT,
+    drag_over: unsafe extern "system" fn(RawPtr, u32, POINTL, *mut u32) -> HRESULT,
+    drag_leave: unsafe extern "system" fn(RawPtr) -> HRESULT,
+    drop: unsafe extern "system" fn(RawPtr, RawPtr, u32, POINTL, *mut u32) -> HRESULT,
+}
+
+static DROP_TARGET_VTBL: DropTargetVtbl = DropTargetVtbl {
+    query_interface,
+    add_ref,
+    release,
+    drag_enter,
+    drag_over,
+    drag_leave,
+    drop,
+};
+
+/// COM object registered with `RegisterDragDrop` that turns file drags over a window
+/// into window events.
+///
+/// OLE calls it on the window's thread while messages are dispatched, so the callbacks
+/// run through the same `window::dispatch` and failure handling as the window
+/// procedure.
+///
+/// The vtable is laid out by hand: the windows crate only offers its `implement` macro
+/// behind the `build` feature, which pulls in the code generator crates this crate
+/// does not depend on.
+#[repr(C)]
+struct DropTarget {
+    vtable: *const DropTargetVtbl,
+    ref_count: AtomicU32,
+    window_handle: HWND,
+    // Whether the current drag carries files; other drags are refused
+    accepting: Cell<bool>,
+    position: Cell<(i32, i32)>,
+}
+
+/// A window registered as a drop target. Dropping it revokes the registration.
+pub(crate) struct DropRegistration {
+    window_handle: HWND,
+    // Dropped in this order: the target needs OLE until its last reference is gone
+    _target: IDropTarget,
+    _ole: OleInitialization,
+}
+
+impl Drop for DropRegistration {
+    fn drop(&mut self) {
+        unsafe {
+            // Releases the reference OLE took on registration
+            let _ = RevokeDragDrop(self.window_handle);
+        }
+    }
+}
+
+/// Keeps OLE initialized on the current thread while alive; each successful
+/// `OleInitialize` must be paired with an `OleUninitialize`.
+struct OleInitialization;
+
+impl OleInitialization {
+    fn new() -> Result<OleInitialization> {
+        unsafe {
+            OleInitialize(ptr::null_mut()).map_err(|e| win_error!(e))?;
+        }
+        Ok(OleInitialization)
+    }
+}
+
+impl Drop for OleInitialization {
+    fn drop(&mut self) {
+        unsafe {
+            OleUninitialize();
+        }
+    }
+}
+
+/// Registers the window to receive dragged files as window events.
+///
+/// Fails if OLE cannot be initialized on the thread, e.g. with `RPC_E_CHANGED_MODE`
+/// when it already joined the multithreaded apartment.
+pub(crate) fn register(window_handle: HWND) -> Result<DropRegistration> {
+    // OLE drag and drop needs OLE initialized on the thread that owns the window
+    let ole = OleInitialization::new()?;
+    let target = create(window_handle);
+    unsafe {
+        RegisterDragDrop(window_handle, &target).map_err(|e| win_error!(e))?;
+    }
+    Ok(DropRegistration {
+        window_handle,
+        _target: target,
+        _ole: ole,
+    })
+}
+
+/// Creates a drop target for the window, owned by the returned reference.
+fn create(window_handle: HWND) -> IDropTarget {
+    let target = Box::new(DropTarget {
+        vtable: &DROP_TARGET_VTBL,
+        ref_count: AtomicU32::new(1),
+        window_handle,
+        accepting: Cell::new(false),
+        position: Cell::new((0, 0)),
+    });
+    // IDropTarget is a transparent wrapper around the interface pointer
+    unsafe { mem::transmute(Box::into_raw(target)) }
+}
+
+impl DropTarget {
+    unsafe fn from_raw<'a>(this: RawPtr) -> &'a DropTarget {
+        &*(this as *const DropTarget)
+    }
+
+    /// Runs a callback on the window's state and returns the drop effect it chose. A
+    /// panic is stored with the window like one in the window procedure, and the drag
+    /// is refused instead.
+    fn handle(&self, callback: impl FnOnce(&WindowState) -> u32) -> u32 {
+        // Targets are only registered for the crate's windows and revoked on WM_DESTROY,
+        // while the HWND still exists
+        let effect = unsafe {
+            window::dispatch(self.window_handle, |state| {
+                state.catch_failure(|| Ok(Some(callback(state))))
+            })
+        };
+        effect.flatten().unwrap_or(DROPEFFECT_NONE)
+    }
+
+    /// Converts the screen position from OLE to client coordinates.
+    fn client_position(&self, point: POINTL) -> (i32, i32) {
+        let mut point = POINT {
+            x: point.x,
+            y: point.y,
+        };
+        unsafe {
+            ScreenToClient(self.window_handle, &mut point);
+        }
+        (point.x, point.y)
+    }
+
+    /// Records the cursor position, reporting it if it changed.
+    fn update_position(&self, state: &WindowState, point: POINTL) {
+        let position = self.client_position(point);
+        if position != self.position.replace(position) {
+            let (x, y) = position;
+            state.push_event(WindowEvent::FileHoverMoved { x, y });
+        }
+    }
+}
+
+/// Paths of the files in the dragged data, `None` if it carries no files.
+unsafe fn dropped_paths(data: RawPtr) -> Option<Vec<PathBuf>> {
+    // Borrowed from OLE, so it must not be released here
+    let data = ManuallyDrop::new(mem::transmute::<RawPtr, Option<IDataObject>>(data));
+    let format = FORMATETC {
+        cfFormat: CF_HDROP as u16,
+        ptd: ptr::null_mut(),
+        dwAspect: DVASPECT_CONTENT as u32,
+        lindex: -1,
+        tymed: TYMED_HGLOBAL as u32,
+    };
+    // ReleaseStgMedium releases everything the medium holds, including pUnkForRelease
+    let mut medium = ManuallyDrop::new(data.as_ref()?.GetData(&format).ok()?);
+    let hdrop = medium.Anonymous.hGlobal;
+
+    // Index 0xFFFFFFFF queries the file count, a null buffer the length of a path
+    let count = DragQueryFileW(hdrop, u32::MAX, PWSTR(ptr::null_mut()), 0);
+    let paths = (0..count)
+        .map(|index| {
+            let len = DragQueryFileW(hdrop, index, PWSTR(ptr::null_mut()), 0);
+            let mut buffer = vec![0u16; len as usize + 1];
+            let len = DragQueryFileW(
+                hdrop,
+                index,
+                PWSTR(buffer.as_mut_ptr()),
+                buffer.len() as u32,
+            );
+            PathBuf::from(os_string_from_wide(&buffer[..len as usize]))
+        })
+        .collect();
+
+    ReleaseStgMedium(&mut *medium);
+    Some(paths)
+}
+
+unsafe extern "system" fn query_interface(
+    this: RawPtr,
+    iid: *const GUID,
+    interface: *mut RawPtr,
+) -> HRESULT {
+    if *iid == IUnknown::IID || *iid == IDropTarget::IID {
+        add_ref(this);
+        *interface = this;
+        S_OK
+    } else {
+        *interface = ptr::null_mut();
+        E_NOINTERFACE
+    }
+}
+
+unsafe extern "system" fn add_ref(this: RawPtr) -> u32 {
+    DropTarget::from_raw(this)
+        .ref_count
+        .fetch_add(1, Ordering::Relaxed)
+        + 1
+}
+
+unsafe extern "system" fn release(this: RawPtr) -> u32 {
+    let count = DropTarget::from_raw(this)
+        .ref_count
+        .fetch_sub(1, Ordering::Release)
+        - 1;
+    if count == 0 {
+        // Pairs with the releasing decrements, so every use through other references
+        // happens before the object is freed
+        fence(Ordering::Acquire);
+        mem::drop(Box::from_raw(this as *mut DropTarget));
+    }
+    count
+}
+
+unsafe extern "system" fn drag_enter(
+    this: RawPtr,
+    data: RawPtr,
+    _key_state: u32,
+    point: POINTL,
+    effect: *mut u32,
+) -> HRESULT {
+    let target = DropTarget::from_raw(this);
+    target.accepting.set(false);
+    *effect = target.handle(|state| match dropped_paths(data) {
+        Some(paths) => {
+            let (x, y) = target.client_position(point);
+            target.accepting.set(true);
+            target.position.set((x, y));
+            state.push_event(WindowEvent::FileHoverEntered { paths, x, y });
+            DROPEFFECT_COPY
+        }
+        None => DROPEFFECT_NONE,
+    });
+    S_OK
+}
+
+unsafe extern "system" fn drag_over(
+    this: RawPtr,
+    _key_state: u32,
+    point: POINTL,
+    effect: *mut u32,
+) -> HRESULT {
+    let target = DropTarget::from_raw(this);
+    *effect = target.handle(|state| {
+        if target.accepting.get() {
+            target.update_position(state, point);
+            DROPEFFECT_COPY
+        } else {
+            DROPEFFECT_NONE
+        }
+    });
+    S_OK
+}
+
+unsafe extern "system" fn drag_leave(this: RawPtr) -> HRESULT {
+    let target = DropTarget::from_raw(this);
+    if target.accepting.replace(false) {
+        target.handle(|state| {
+            state.push_event(WindowEvent::FileHoverLeft);
+            DROPEFFECT_NONE
+        });
+    }
+    S_OK
+}
+
+unsafe extern "system" fn drop(
+    this: RawPtr,
+    data: RawPtr,
+    _key_state: u32,
+    point: POINTL,
+    effect: *mut u32,
+) -> HRESULT {
+    let target = DropTarget::from_raw(this);
+    let accepting = target.accepting.replace(false);
+    *effect = target.handle(|state| match dropped_paths(data).filter(|_| accepting) {
+        Some(paths) => {
+            target.update_position(state, point);
+            state.push_event(WindowEvent::FileDropped(paths));
+            DROPEFFECT_COPY
+        }
+        None => DROPEFFECT_NONE,
+    });
+    S_OK
+}
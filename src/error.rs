@@ -1,5 +1,7 @@
 use std::{fmt, error};
-use windows::core::Error;
+use std::panic::Location;
+use windows::core::{Error, HRESULT};
+use windows::Win32::Foundation::E_INVALIDARG;
 
 /// The error type for when the OS cannot perform the requested operation.
 #[derive(Debug)]
@@ -7,17 +9,47 @@ pub struct Win32Error {
     line: u32,
     file: &'static str,
     error: Error,
+    // Set for failures the crate detects itself, which the OS has no text for
+    message: Option<String>,
 }
 
 impl Win32Error {
     #[allow(dead_code)]
     pub(crate) fn new(line: u32, file: &'static str, error: Error) -> Win32Error {
-        Win32Error { line, file, error }
+        Win32Error {
+            line,
+            file,
+            error,
+            message: None,
+        }
+    }
+
+    /// Error with `code` and a description, for failures the crate detects rather than
+    /// the OS reports.
+    #[track_caller]
+    pub(crate) fn with_message(code: HRESULT, message: impl Into<String>) -> Win32Error {
+        let location = Location::caller();
+        Win32Error {
+            line: location.line(),
+            file: location.file(),
+            error: Error::fast_error(code),
+            message: Some(message.into()),
+        }
+    }
+
+    /// `E_INVALIDARG` error for an argument or message the crate rejected.
+    #[track_caller]
+    pub(crate) fn invalid_argument(message: impl Into<String>) -> Win32Error {
+        Win32Error::with_message(E_INVALIDARG, message)
+    }
+
+    /// The error code describing the error.
+    pub fn code(&self) -> HRESULT {
+        self.error.code()
     }
 }
 
 #[allow(unused_macros)]
-#[macro_export]
 macro_rules! win_error {
     ($error:expr) => {{
             crate::error::Win32Error::new(line!(), file!(), $error)
@@ -26,10 +58,16 @@ macro_rules! win_error {
 
 impl fmt::Display for Win32Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
-        f.pad(&format!(
-            "os error at {}:{}: {}",
-            self.file, self.line, self.error
-        ))
+        match &self.message {
+            Some(message) => f.pad(&format!(
+                "os error at {}:{}: {}",
+                self.file, self.line, message
+            )),
+            None => f.pad(&format!(
+                "os error at {}:{}: {}",
+                self.file, self.line, self.error
+            )),
+        }
     }
 }
 
@@ -0,0 +1,51 @@
+use std::path::PathBuf;
+
+use crate::dpi::PhysicalSize;
+use crate::keyboard;
+use crate::mouse;
+
+/// Everything a window reports to the application, in the order the OS delivered it.
+///
+/// The window procedure only translates messages into these events; what to do
+/// with them is up to the code draining the queue after the message pump ran.
+#[derive(Clone, Debug)]
+pub enum WindowEvent {
+    /// A key was pressed or released.
+    Key(keyboard::Event),
+    /// A UTF-16 code unit of typed text, as delivered by WM_CHAR.
+    Char(u16),
+    /// Button, wheel, movement, enter/leave and hit-test region events.
+    Mouse(mouse::Event),
+    /// The client area changed size, in physical pixels. Zero when minimized.
+    Resized { width: i32, height: i32 },
+    /// The window gained (`true`) or lost (`false`) keyboard focus.
+    Focused(bool),
+    /// The window was minimized (`true`) or restored from being minimized (`false`).
+    Minimized(bool),
+    /// The window became completely hidden behind other windows or the lock screen
+    /// (`true`), or visible again (`false`). Detected when presenting a frame.
+    Occluded(bool),
+    /// The fullscreen hotkey (Alt+Enter) was pressed; the application applies it with
+    /// `Window::toggle_fullscreen` once message processing is done.
+    FullscreenToggleRequested,
+    /// The window moved to a monitor with a different DPI and has been resized to the
+    /// rectangle suggested by the OS; `size` is the resulting client area size.
+    ScaleFactorChanged { scale_factor: f32, size: PhysicalSize },
+    /// Files are dragged over the window; `x` and `y` are the cursor position in
+    /// client coordinates.
+    FileHoverEntered { paths: Vec<PathBuf>, x: i32, y: i32 },
+    /// The cursor moved while dragging files over the window.
+    FileHoverMoved { x: i32, y: i32 },
+    /// The drag left the window or was cancelled.
+    FileHoverLeft,
+    /// Files were dropped on the window, at the position of the last hover event.
+    FileDropped(Vec<PathBuf>),
+    /// The content of the system clipboard changed, possibly by this application.
+    ClipboardChanged,
+    /// The user asked to close the window (close button, Alt+F4) or
+    /// `Window::request_close` was called. The window stays open unless
+    /// `Application::on_close_requested` agrees.
+    CloseRequested,
+    /// The OS window is gone; the `Window` should be dropped.
+    Destroyed,
+}
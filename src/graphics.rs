@@ -1,7 +1,9 @@
+use std::fmt;
 use std::ptr;
 
+use windows::core::Interface;
 use windows::Win32::{
-    Foundation::{BOOL, HINSTANCE, HWND, PSTR, PWSTR},
+    Foundation::{BOOL, DXGI_STATUS_OCCLUDED, HINSTANCE, HWND, PSTR},
     Graphics::{
         Direct3D::{
             Fxc::{D3DCompileFromFile, D3DCOMPILE_DEBUG, D3DCOMPILE_SKIP_OPTIMIZATION},
@@ -18,31 +20,125 @@ use windows::Win32::{
         },
         Dxgi::{
             Common::{
-                DXGI_FORMAT_B8G8R8A8_UNORM, DXGI_FORMAT_R32G32_FLOAT, DXGI_MODE_DESC,
-                DXGI_MODE_SCALING_UNSPECIFIED, DXGI_MODE_SCANLINE_ORDER_UNSPECIFIED, DXGI_RATIONAL,
-                DXGI_SAMPLE_DESC,
+                DXGI_FORMAT_B8G8R8A8_UNORM, DXGI_FORMAT_R32G32_FLOAT, DXGI_FORMAT_UNKNOWN,
+                DXGI_MODE_DESC, DXGI_MODE_SCALING_UNSPECIFIED,
+                DXGI_MODE_SCANLINE_ORDER_UNSPECIFIED, DXGI_RATIONAL, DXGI_SAMPLE_DESC,
             },
-            IDXGISwapChain, DXGI_ERROR_DEVICE_REMOVED, DXGI_SWAP_CHAIN_DESC,
-            DXGI_SWAP_EFFECT_DISCARD, DXGI_USAGE_RENDER_TARGET_OUTPUT,
+            IDXGIFactory, IDXGIOutput, IDXGISwapChain, DXGI_ERROR_DEVICE_REMOVED,
+            DXGI_MWA_NO_ALT_ENTER, DXGI_PRESENT_TEST, DXGI_SWAP_CHAIN_DESC,
+            DXGI_SWAP_CHAIN_FLAG_ALLOW_MODE_SWITCH, DXGI_SWAP_EFFECT_DISCARD,
+            DXGI_USAGE_RENDER_TARGET_OUTPUT,
         },
     },
 };
 
-use crate::{error::Win32Error, win32_common::ToWide};
+use crate::{error::Win32Error, wide_string::WideCString};
 pub type Result<T> = core::result::Result<T, Win32Error>;
 
+/// A resolution and refresh rate supported by a display output.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct DisplayMode {
+    pub width: u32,
+    pub height: u32,
+    /// Refresh rate in hertz as the rational number `numerator / denominator`.
+    pub refresh_rate_numerator: u32,
+    pub refresh_rate_denominator: u32,
+}
+
+impl DisplayMode {
+    pub fn refresh_rate(&self) -> f32 {
+        if self.refresh_rate_denominator == 0 {
+            return 0.0;
+        }
+        self.refresh_rate_numerator as f32 / self.refresh_rate_denominator as f32
+    }
+
+    /// Parses the `Display` form, e.g. `1920x1080@60000/1001`.
+    pub fn parse(text: &str) -> Option<DisplayMode> {
+        let (size, refresh_rate) = text.trim().split_once('@')?;
+        let (width, height) = size.split_once('x')?;
+        let (numerator, denominator) = refresh_rate.split_once('/')?;
+        Some(DisplayMode {
+            width: width.parse().ok()?,
+            height: height.parse().ok()?,
+            refresh_rate_numerator: numerator.parse().ok()?,
+            refresh_rate_denominator: denominator.parse().ok()?,
+        })
+    }
+
+    fn from_desc(desc: &DXGI_MODE_DESC) -> DisplayMode {
+        DisplayMode {
+            width: desc.Width,
+            height: desc.Height,
+            refresh_rate_numerator: desc.RefreshRate.Numerator,
+            refresh_rate_denominator: desc.RefreshRate.Denominator,
+        }
+    }
+
+    fn to_desc(self) -> DXGI_MODE_DESC {
+        DXGI_MODE_DESC {
+            Width: self.width,
+            Height: self.height,
+            RefreshRate: DXGI_RATIONAL {
+                Numerator: self.refresh_rate_numerator,
+                Denominator: self.refresh_rate_denominator,
+            },
+            Format: DXGI_FORMAT_B8G8R8A8_UNORM,
+            ScanlineOrdering: DXGI_MODE_SCANLINE_ORDER_UNSPECIFIED,
+            Scaling: DXGI_MODE_SCALING_UNSPECIFIED,
+        }
+    }
+}
+
+/// Resolution and exact refresh rate, e.g. `1920x1080@60000/1001` for 59.94 Hz.
+impl fmt::Display for DisplayMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}x{}@{}/{}",
+            self.width, self.height, self.refresh_rate_numerator, self.refresh_rate_denominator
+        )
+    }
+}
+
+/// Display modes of a DXGI output in the back buffer format.
+pub(crate) fn output_display_modes(output: &IDXGIOutput) -> Result<Vec<DisplayMode>> {
+    unsafe {
+        let mut count = 0;
+        output
+            .GetDisplayModeList(DXGI_FORMAT_B8G8R8A8_UNORM, 0, &mut count, ptr::null_mut())
+            .map_err(|e| win_error!(e))?;
+        let mut descs = vec![DXGI_MODE_DESC::default(); count as usize];
+        output
+            .GetDisplayModeList(
+                DXGI_FORMAT_B8G8R8A8_UNORM,
+                0,
+                &mut count,
+                descs.as_mut_ptr(),
+            )
+            .map_err(|e| win_error!(e))?;
+        descs.truncate(count as usize);
+        Ok(descs.iter().map(DisplayMode::from_desc).collect())
+    }
+}
+
+// Exclusive fullscreen needs the swap chain to be allowed to change the display mode.
+const SWAP_CHAIN_FLAGS: u32 = DXGI_SWAP_CHAIN_FLAG_ALLOW_MODE_SWITCH as u32;
+
 pub struct Graphics {
     window_handle: HWND,
     device: ID3D11Device,
     swap_chain: IDXGISwapChain,
     device_context: ID3D11DeviceContext,
-    render_target_view: ID3D11RenderTargetView,
+    render_target_view: Option<ID3D11RenderTargetView>,
+    width: u32,
+    height: u32,
     vertex_shader_blob: ID3DBlob,
     pixel_shader_blob: ID3DBlob,
 }
 
 impl Graphics {
-    pub fn new(window_handle: HWND) -> Result<Self> {
+    pub fn new(window_handle: HWND, width: u32, height: u32) -> Result<Self> {
         unsafe {
             let mut device: Option<ID3D11Device> = None;
             let mut swap_chain: Option<IDXGISwapChain> = None;
@@ -55,8 +151,8 @@ impl Graphics {
                     };
 
                     DXGI_MODE_DESC {
-                        Width: 0,
-                        Height: 0,
+                        Width: width,
+                        Height: height,
                         RefreshRate: refresh_rate,
                         Format: DXGI_FORMAT_B8G8R8A8_UNORM,
                         ScanlineOrdering: DXGI_MODE_SCANLINE_ORDER_UNSPECIFIED,
@@ -77,7 +173,7 @@ impl Graphics {
                     OutputWindow: window_handle,
                     Windowed: BOOL(1),
                     SwapEffect: DXGI_SWAP_EFFECT_DISCARD,
-                    Flags: 0,
+                    Flags: SWAP_CHAIN_FLAGS,
                 }
             };
 
@@ -97,31 +193,27 @@ impl Graphics {
             )
             .map_err(|e| win_error!(e))?;
 
-            let buffer = swap_chain
-                .as_mut()
+            // Alt+Enter is handled by `Window` so it can track the fullscreen state itself
+            swap_chain
+                .as_ref()
                 .unwrap()
-                .GetBuffer::<ID3D11Resource>(0)
+                .GetParent::<IDXGIFactory>()
+                .and_then(|factory| {
+                    factory.MakeWindowAssociation(window_handle, DXGI_MWA_NO_ALT_ENTER)
+                })
                 .map_err(|e| win_error!(e))?;
-            let render_target_view = {
-                //     let target_view_desc = D3D11_RENDER_TARGET_VIEW_DESC {
-                //         Format: DXGI_FORMAT_B8G8R8A8_UNORM,
-                //         ViewDimension: D3D11_RTV_DIMENSION_TEXTURE2D,
-                //         ..mem::zeroed()
-                //     };
-
-                device
-                    .as_mut()
-                    .unwrap()
-                    .CreateRenderTargetView(buffer, ptr::null())
-                    .map_err(|e| win_error!(e))?
-            };
+
+            let render_target_view = Self::create_render_target_view(
+                device.as_ref().unwrap(),
+                swap_chain.as_ref().unwrap(),
+            )?;
 
             // Create vertex shader
             let exe_path = std::env::current_exe().ok().unwrap();
             let asset_path = exe_path.parent().unwrap();
             let shaders_hlsl_path = asset_path.join("shaders.hlsl");
-            let shaders_hlsl = shaders_hlsl_path.to_str().unwrap();
-            println!("shader at: {shaders_hlsl}");
+            println!("shader at: {}", shaders_hlsl_path.display());
+            let shaders_hlsl = WideCString::try_from(shaders_hlsl_path.as_path())?;
 
             let compile_flags = if cfg!(debug_assertions) {
                 D3DCOMPILE_DEBUG | D3DCOMPILE_SKIP_OPTIMIZATION
@@ -131,7 +223,7 @@ impl Graphics {
 
             let mut vertex_shader_blob = None;
             let vertex_shader_blob = D3DCompileFromFile(
-                PWSTR(shaders_hlsl.to_wide().as_mut_ptr()),
+                &shaders_hlsl,
                 std::ptr::null_mut(),
                 None,
                 PSTR(b"VSMain\0".as_ptr() as *mut u8),
@@ -147,7 +239,7 @@ impl Graphics {
             // Create pixel shader
             let mut pixel_shader_blob = None;
             let pixel_shader_blob = D3DCompileFromFile(
-                PWSTR(shaders_hlsl.to_wide().as_mut_ptr()),
+                &shaders_hlsl,
                 std::ptr::null_mut(),
                 None,
                 PSTR(b"PSMain\0".as_ptr() as *mut u8),
@@ -165,16 +257,136 @@ impl Graphics {
                 device: device.unwrap(),
                 swap_chain: swap_chain.unwrap(),
                 device_context: device_context.unwrap(),
-                render_target_view,
+                render_target_view: Some(render_target_view),
+                width,
+                height,
                 vertex_shader_blob,
                 pixel_shader_blob,
             })
         }
     }
 
-    pub fn present_frame(&self) -> Result<()> {
+    fn create_render_target_view(
+        device: &ID3D11Device,
+        swap_chain: &IDXGISwapChain,
+    ) -> Result<ID3D11RenderTargetView> {
         unsafe {
-            self.swap_chain.Present(1, 0).map_err(|e| {
+            let buffer = swap_chain
+                .GetBuffer::<ID3D11Resource>(0)
+                .map_err(|e| win_error!(e))?;
+            device
+                .CreateRenderTargetView(buffer, ptr::null())
+                .map_err(|e| win_error!(e))
+        }
+    }
+
+    /// Resizes the swap chain buffers to the new client area size.
+    ///
+    /// All references to the back buffer must be released before `ResizeBuffers`,
+    /// so the render target view is unbound and dropped first and recreated afterwards.
+    pub fn resize(&mut self, width: u32, height: u32) -> Result<()> {
+        // A minimized window reports a zero sized client area; keep the old buffers.
+        if width == 0 || height == 0 || (width == self.width && height == self.height) {
+            return Ok(());
+        }
+        unsafe {
+            self.device_context
+                .OMSetRenderTargets(0, ptr::null(), None);
+            self.render_target_view = None;
+            self.swap_chain
+                .ResizeBuffers(0, width, height, DXGI_FORMAT_UNKNOWN, SWAP_CHAIN_FLAGS)
+                .map_err(|e| win_error!(e))?;
+        }
+        self.render_target_view = Some(Self::create_render_target_view(
+            &self.device,
+            &self.swap_chain,
+        )?);
+        self.width = width;
+        self.height = height;
+        Ok(())
+    }
+
+    /// Size of the back buffer in pixels.
+    pub fn size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    /// Display modes of the output the window is currently on, in the back buffer format.
+    pub fn display_modes(&self) -> Result<Vec<DisplayMode>> {
+        unsafe {
+            let output = self
+                .swap_chain
+                .GetContainingOutput()
+                .map_err(|e| win_error!(e))?;
+            output_display_modes(&output)
+        }
+    }
+
+    /// Enters exclusive fullscreen with the given display mode, or leaves it with `None`.
+    ///
+    /// DXGI resizes the window, so the back buffers are resized through the regular
+    /// `WindowEvent::Resized` path afterwards.
+    pub fn set_exclusive_fullscreen(&mut self, mode: Option<DisplayMode>) -> Result<()> {
+        unsafe {
+            match mode {
+                Some(mode) => {
+                    let desc = mode.to_desc();
+                    self.swap_chain
+                        .ResizeTarget(&desc)
+                        .map_err(|e| win_error!(e))?;
+                    self.swap_chain
+                        .SetFullscreenState(BOOL(1), None)
+                        .map_err(|e| win_error!(e))?;
+                    // Recommended after the switch so the refresh rate does not get stuck
+                    let desc = DXGI_MODE_DESC {
+                        RefreshRate: DXGI_RATIONAL::default(),
+                        ..desc
+                    };
+                    self.swap_chain
+                        .ResizeTarget(&desc)
+                        .map_err(|e| win_error!(e))
+                }
+                None => self
+                    .swap_chain
+                    .SetFullscreenState(BOOL(0), None)
+                    .map_err(|e| win_error!(e)),
+            }
+        }
+    }
+
+    pub fn is_exclusive_fullscreen(&self) -> bool {
+        let mut fullscreen = BOOL(0);
+        unsafe {
+            let _ = self
+                .swap_chain
+                .GetFullscreenState(&mut fullscreen, ptr::null_mut());
+        }
+        fullscreen.as_bool()
+    }
+
+    /// Presents the back buffer. Returns `false` if the window is occluded, in which
+    /// case nothing was shown and rendering can pause until `test_present` succeeds.
+    pub fn present_frame(&self) -> Result<bool> {
+        self.present(1, 0)
+    }
+
+    /// Checks whether a present would currently be visible, without presenting.
+    pub fn test_present(&self) -> Result<bool> {
+        self.present(0, DXGI_PRESENT_TEST)
+    }
+
+    fn present(&self, sync_interval: u32, flags: u32) -> Result<bool> {
+        unsafe {
+            // The generated `Present` wrapper drops success codes such as DXGI_STATUS_OCCLUDED
+            let hresult = (self.swap_chain.vtable().8)(
+                std::mem::transmute_copy(&self.swap_chain),
+                sync_interval,
+                flags,
+            );
+            if hresult == DXGI_STATUS_OCCLUDED {
+                return Ok(false);
+            }
+            hresult.ok().map_err(|e| {
                 if let Some(hresult) = e.win32_error() {
                     if hresult == DXGI_ERROR_DEVICE_REMOVED.0.try_into().unwrap() {
                         println!("{:?}", self.device.GetDeviceRemovedReason());
@@ -183,14 +395,16 @@ impl Graphics {
                 win_error!(e)
             })?;
         }
-        Ok(())
+        Ok(true)
     }
 
     pub fn clear_buffer(&mut self, red: f32, green: f32, blue: f32) {
         let colorrgba = [red, green, blue, 1.0].as_ptr();
-        unsafe {
-            self.device_context
-                .ClearRenderTargetView(&self.render_target_view, colorrgba);
+        if let Some(render_target_view) = &self.render_target_view {
+            unsafe {
+                self.device_context
+                    .ClearRenderTargetView(render_target_view, colorrgba);
+            }
         }
     }
 
@@ -308,7 +522,7 @@ impl Graphics {
                 .map_err(|e| win_error!(e))?;
             self.device_context.OMSetRenderTargets(
                 1,
-                &Some(self.render_target_view.to_owned()),
+                &self.render_target_view,
                 &depth_stencil_view,
             );
         }
@@ -323,8 +537,8 @@ impl Graphics {
         let vp = D3D11_VIEWPORT {
             TopLeftX: 0.0,
             TopLeftY: 0.0,
-            Width: 800.0,
-            Height: 600.0,
+            Width: self.width as f32,
+            Height: self.height as f32,
             MinDepth: 0.0,
             MaxDepth: 1.0,
         };
@@ -340,6 +554,15 @@ impl Graphics {
     }
 }
 
+impl Drop for Graphics {
+    fn drop(&mut self) {
+        // A swap chain must not be released while in exclusive fullscreen
+        unsafe {
+            let _ = self.swap_chain.SetFullscreenState(BOOL(0), None);
+        }
+    }
+}
+
 struct Vertex {
     x: f32,
     y: f32,
@@ -350,3 +573,47 @@ const TRIANGLE: [Vertex; 3] = [
     Vertex { x: 0.5, y: 0.5 },
     Vertex { x: -0.5, y: -0.5 },
 ];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn display_mode_round_trip() {
+        let mode = DisplayMode {
+            width: 1920,
+            height: 1080,
+            refresh_rate_numerator: 60000,
+            refresh_rate_denominator: 1001,
+        };
+        assert_eq!(mode.to_string(), "1920x1080@60000/1001");
+        assert_eq!(DisplayMode::parse(&mode.to_string()), Some(mode));
+        assert_eq!(DisplayMode::parse(" 1920x1080@60000/1001 "), Some(mode));
+        assert!((mode.refresh_rate() - 59.94).abs() < 0.01);
+    }
+
+    #[test]
+    fn display_mode_parse_rejects_malformed_input() {
+        for text in [
+            "",
+            "1920x1080",
+            "1920x1080@60",
+            "1920@60/1",
+            "1920x@60/1",
+            "1920x1080@/1",
+            "1920x1080@60/1,",
+            "1920x1080@60/1/1",
+            "wide x1080@60/1",
+            "-1920x1080@60/1",
+            "1920x1080@59.94/1",
+        ] {
+            assert_eq!(DisplayMode::parse(text), None, "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn zero_denominator_means_no_refresh_rate() {
+        let mode = DisplayMode::parse("800x600@0/0").unwrap();
+        assert_eq!(mode.refresh_rate(), 0.0);
+    }
+}
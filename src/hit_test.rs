@@ -0,0 +1,248 @@
+/// Handle to a region registered with the `Mouse`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct RegionId(u32);
+
+/// Area of the client region, in physical pixels, that reacts to the mouse.
+pub enum Shape {
+    /// Half-open rectangle: `left <= x < right` and `top <= y < bottom`.
+    Rect {
+        left: isize,
+        top: isize,
+        right: isize,
+        bottom: isize,
+    },
+    Circle {
+        center_x: isize,
+        center_y: isize,
+        radius: isize,
+    },
+    /// Any user supplied containment test.
+    Custom(Box<dyn Fn(isize, isize) -> bool>),
+}
+
+impl Shape {
+    pub fn contains(&self, x: isize, y: isize) -> bool {
+        match self {
+            Shape::Rect {
+                left,
+                top,
+                right,
+                bottom,
+            } => x >= *left && x < *right && y >= *top && y < *bottom,
+            Shape::Circle {
+                center_x,
+                center_y,
+                radius,
+            } => {
+                let dx = x - center_x;
+                let dy = y - center_y;
+                dx * dx + dy * dy <= radius * radius
+            }
+            Shape::Custom(contains) => contains(x, y),
+        }
+    }
+}
+
+struct Region {
+    id: RegionId,
+    name: String,
+    shape: Shape,
+    z_order: i32,
+}
+
+/// Change of the hovered region caused by a mouse move or a region update.
+pub struct HoverChange {
+    pub left: Option<RegionId>,
+    pub entered: Option<RegionId>,
+}
+
+/// Set of named regions and the one currently under the mouse.
+///
+/// Regions with a higher z-order are on top; among equal z-orders the region
+/// added last wins. Only the topmost region under the cursor is hovered.
+pub struct HitTester {
+    next_id: u32,
+    regions: Vec<Region>,
+    hovered: Option<RegionId>,
+}
+
+impl HitTester {
+    pub fn new() -> HitTester {
+        HitTester {
+            next_id: 0,
+            regions: Vec::new(),
+            hovered: None,
+        }
+    }
+
+    pub fn add(&mut self, name: &str, shape: Shape, z_order: i32) -> RegionId {
+        let id = RegionId(self.next_id);
+        self.next_id += 1;
+        // Keep regions sorted from top to bottom so hit testing can stop at the first match
+        let index = self
+            .regions
+            .iter()
+            .position(|region| region.z_order <= z_order)
+            .unwrap_or(self.regions.len());
+        self.regions.insert(
+            index,
+            Region {
+                id,
+                name: name.into(),
+                shape,
+                z_order,
+            },
+        );
+        id
+    }
+
+    /// Removes the region, reporting a hover leave if it was under the mouse.
+    pub fn remove(&mut self, id: RegionId) -> HoverChange {
+        self.regions.retain(|region| region.id != id);
+        if self.hovered == Some(id) {
+            self.hovered = None;
+            HoverChange {
+                left: Some(id),
+                entered: None,
+            }
+        } else {
+            HoverChange {
+                left: None,
+                entered: None,
+            }
+        }
+    }
+
+    pub fn name(&self, id: RegionId) -> Option<&str> {
+        self.regions
+            .iter()
+            .find(|region| region.id == id)
+            .map(|region| region.name.as_str())
+    }
+
+    pub fn find(&self, name: &str) -> Option<RegionId> {
+        self.regions
+            .iter()
+            .find(|region| region.name == name)
+            .map(|region| region.id)
+    }
+
+    pub fn hovered(&self) -> Option<RegionId> {
+        self.hovered
+    }
+
+    /// Topmost region containing the point.
+    pub fn hit(&self, x: isize, y: isize) -> Option<RegionId> {
+        self.regions
+            .iter()
+            .find(|region| region.shape.contains(x, y))
+            .map(|region| region.id)
+    }
+
+    /// Updates the hovered region for a new cursor position, `None` meaning the
+    /// cursor left the client area.
+    pub fn update(&mut self, position: Option<(isize, isize)>) -> HoverChange {
+        let hit = position.and_then(|(x, y)| self.hit(x, y));
+        if hit == self.hovered {
+            return HoverChange {
+                left: None,
+                entered: None,
+            };
+        }
+        let left = self.hovered;
+        self.hovered = hit;
+        HoverChange { left, entered: hit }
+    }
+}
+
+impl Default for HitTester {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rect(left: isize, top: isize, right: isize, bottom: isize) -> Shape {
+        Shape::Rect {
+            left,
+            top,
+            right,
+            bottom,
+        }
+    }
+
+    #[test]
+    fn topmost_region_wins_on_overlap() {
+        let mut tester = HitTester::new();
+        let top = tester.add("top", rect(0, 0, 10, 10), 2);
+        let bottom = tester.add("bottom", rect(0, 0, 20, 20), 1);
+        assert_eq!(tester.hit(5, 5), Some(top));
+        assert_eq!(tester.hit(15, 15), Some(bottom));
+        assert_eq!(tester.hit(20, 20), None);
+    }
+
+    #[test]
+    fn equal_z_order_prefers_the_region_added_last() {
+        let mut tester = HitTester::new();
+        let first = tester.add("first", rect(0, 0, 10, 10), 0);
+        let second = tester.add(
+            "second",
+            Shape::Circle {
+                center_x: 5,
+                center_y: 5,
+                radius: 3,
+            },
+            0,
+        );
+        assert_eq!(tester.hit(5, 5), Some(second));
+        assert_eq!(tester.hit(0, 0), Some(first));
+        let third = tester.add("third", Shape::Custom(Box::new(|x, _| x == 5)), 0);
+        assert_eq!(tester.hit(5, 5), Some(third));
+    }
+
+    #[test]
+    fn removed_region_no_longer_hits() {
+        let mut tester = HitTester::new();
+        let bottom = tester.add("bottom", rect(0, 0, 10, 10), 0);
+        let top = tester.add("top", rect(0, 0, 10, 10), 1);
+        let change = tester.remove(top);
+        assert_eq!((change.left, change.entered), (None, None));
+        assert_eq!(tester.hit(5, 5), Some(bottom));
+        assert_eq!(tester.find("top"), None);
+        assert_eq!(tester.name(top), None);
+        assert_eq!(tester.name(bottom), Some("bottom"));
+    }
+
+    #[test]
+    fn removing_the_hovered_region_reports_a_leave() {
+        let mut tester = HitTester::new();
+        let region = tester.add("region", rect(0, 0, 10, 10), 0);
+        tester.update(Some((5, 5)));
+        let change = tester.remove(region);
+        assert_eq!((change.left, change.entered), (Some(region), None));
+        assert_eq!(tester.hovered(), None);
+    }
+
+    #[test]
+    fn update_reports_hover_changes() {
+        let mut tester = HitTester::new();
+        let a = tester.add("a", rect(0, 0, 10, 10), 0);
+        let b = tester.add("b", rect(10, 0, 20, 10), 0);
+
+        let change = tester.update(Some((5, 5)));
+        assert_eq!((change.left, change.entered), (None, Some(a)));
+        let change = tester.update(Some((6, 6)));
+        assert_eq!((change.left, change.entered), (None, None));
+        let change = tester.update(Some((15, 5)));
+        assert_eq!((change.left, change.entered), (Some(a), Some(b)));
+        let change = tester.update(Some((50, 50)));
+        assert_eq!((change.left, change.entered), (Some(b), None));
+        tester.update(Some((15, 5)));
+        let change = tester.update(None);
+        assert_eq!((change.left, change.entered), (Some(b), None));
+        assert_eq!(tester.hovered(), None);
+    }
+}
@@ -0,0 +1,356 @@
+use std::cmp::Reverse;
+use std::fs;
+use std::path::Path;
+
+use windows::core::Error;
+use windows::Win32::Foundation::{E_FAIL, PWSTR};
+use windows::Win32::System::LibraryLoader::GetModuleHandleW;
+use windows::Win32::UI::HiDpi::GetSystemMetricsForDpi;
+use windows::Win32::UI::WindowsAndMessaging::{
+    CreateIconFromResourceEx, DestroyIcon, LoadImageW, HICON, IMAGE_ICON, LR_DEFAULTCOLOR,
+    LR_DEFAULTSIZE, LR_SHARED, SM_CXICON, SM_CXSMICON,
+};
+
+use crate::error::Win32Error;
+pub type Result<T> = core::result::Result<T, Win32Error>;
+
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+const ICONDIR_SIZE: usize = 6;
+const ICONDIRENTRY_SIZE: usize = 16;
+/// Resource format version `CreateIconFromResourceEx` expects for icons.
+const ICON_RESOURCE_VERSION: u32 = 0x0003_0000;
+
+/// Window and taskbar icon.
+pub struct Icon {
+    handle: HICON,
+    // Shared icons belong to the system and must not be destroyed
+    owned: bool,
+}
+
+impl Icon {
+    /// Icon resource with the given id embedded in the executable.
+    pub fn from_resource(id: u16) -> Result<Icon> {
+        unsafe {
+            // MAKEINTRESOURCEW: the id is passed in place of the name pointer
+            let handle = LoadImageW(
+                GetModuleHandleW(None),
+                PWSTR(id as usize as *mut u16),
+                IMAGE_ICON,
+                0,
+                0,
+                LR_DEFAULTSIZE | LR_SHARED,
+            );
+            if handle.is_invalid() {
+                return Err(win_error!(Error::from_win32()));
+            }
+            Ok(Icon {
+                handle: handle.0,
+                owned: false,
+            })
+        }
+    }
+
+    /// Icon of `size` pixels square from the contents of an ICO file, using the image
+    /// that fits `size` best, see `best_image`.
+    pub fn from_ico(bytes: &[u8], size: u32) -> Result<Icon> {
+        let images =
+            parse_ico(bytes).ok_or_else(|| Win32Error::invalid_argument("invalid ICO data"))?;
+        let image = best_image(&images, size)
+            .ok_or_else(|| Win32Error::invalid_argument("ICO data contains no images"))?;
+        Icon::from_image_data(&image.data, size)
+    }
+
+    /// Icon of `size` pixels square from the contents of a PNG file, scaled if needed.
+    pub fn from_png(bytes: &[u8], size: u32) -> Result<Icon> {
+        if !is_png(bytes) {
+            return Err(Win32Error::invalid_argument("invalid PNG data"));
+        }
+        Icon::from_image_data(bytes, size)
+    }
+
+    /// Icon from a PNG or ICO file, told apart by their contents.
+    pub fn from_file(path: &Path, size: u32) -> Result<Icon> {
+        let bytes = fs::read(path).map_err(|e| Win32Error::with_message(E_FAIL, e.to_string()))?;
+        if is_png(&bytes) {
+            Icon::from_png(&bytes, size)
+        } else {
+            Icon::from_ico(&bytes, size)
+        }
+    }
+
+    /// Size in pixels of the icon in the title bar at `dpi`.
+    pub fn small_size(dpi: u32) -> u32 {
+        unsafe { GetSystemMetricsForDpi(SM_CXSMICON as i32, dpi) as u32 }
+    }
+
+    /// Size in pixels of the icon in the taskbar and the Alt+Tab switcher at `dpi`.
+    pub fn large_size(dpi: u32) -> u32 {
+        unsafe { GetSystemMetricsForDpi(SM_CXICON as i32, dpi) as u32 }
+    }
+
+    /// `data` is a PNG image or a DIB as stored in ICO files.
+    fn from_image_data(data: &[u8], size: u32) -> Result<Icon> {
+        let handle = unsafe {
+            CreateIconFromResourceEx(
+                data.as_ptr(),
+                data.len() as u32,
+                true,
+                ICON_RESOURCE_VERSION,
+                size as i32,
+                size as i32,
+                LR_DEFAULTCOLOR,
+            )
+        };
+        if handle == 0 {
+            return Err(win_error!(Error::from_win32()));
+        }
+        Ok(Icon {
+            handle,
+            owned: true,
+        })
+    }
+
+    pub(crate) fn handle(&self) -> HICON {
+        self.handle
+    }
+}
+
+impl Drop for Icon {
+    fn drop(&mut self) {
+        if self.owned {
+            unsafe {
+                DestroyIcon(self.handle);
+            }
+        }
+    }
+}
+
+/// One image of an ICO file.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct IconImage {
+    pub width: u32,
+    pub height: u32,
+    /// Bits per pixel; 32 for PNG images that do not state it.
+    pub bit_count: u16,
+    /// A PNG image or a DIB with the color and mask bitmaps.
+    pub data: Vec<u8>,
+}
+
+impl IconImage {
+    pub fn is_png(&self) -> bool {
+        is_png(&self.data)
+    }
+}
+
+/// Images of an ICO file in directory order. Returns `None` if `bytes` is not an icon
+/// file or an image lies outside of it.
+pub fn parse_ico(bytes: &[u8]) -> Option<Vec<IconImage>> {
+    // ICONDIR: reserved, type (1 for icons, 2 for cursors) and image count
+    if bytes.len() < ICONDIR_SIZE || read_u16(bytes, 0)? != 0 || read_u16(bytes, 2)? != 1 {
+        return None;
+    }
+    let count = read_u16(bytes, 4)? as usize;
+    (0..count)
+        .map(|index| {
+            // ICONDIRENTRY: sizes of 0 stand for 256
+            let entry = bytes.get(ICONDIR_SIZE + index * ICONDIRENTRY_SIZE..)?;
+            let width = dimension(*entry.first()?);
+            let height = dimension(*entry.get(1)?);
+            let entry_bit_count = read_u16(entry, 6)?;
+            let size = read_u32(entry, 8)? as usize;
+            let offset = read_u32(entry, 12)? as usize;
+            let data = bytes.get(offset..offset.checked_add(size)?)?;
+
+            if is_png(data) {
+                // IHDR is the first chunk, it starts with the big-endian size
+                let width = u32::from_be_bytes(data.get(16..20)?.try_into().ok()?);
+                let height = u32::from_be_bytes(data.get(20..24)?.try_into().ok()?);
+                let bit_count = match entry_bit_count {
+                    0 => 32,
+                    bit_count => bit_count,
+                };
+                return Some(IconImage {
+                    width,
+                    height,
+                    bit_count,
+                    data: data.to_vec(),
+                });
+            }
+            // BITMAPINFOHEADER, whose height covers both the color and the mask bitmap
+            if read_u32(data, 0)? < 40 {
+                return None;
+            }
+            Some(IconImage {
+                width,
+                height,
+                bit_count: read_u16(data, 14)?,
+                data: data.to_vec(),
+            })
+        })
+        .collect()
+}
+
+/// The image to scale to `size` pixels square: the smallest one at least that large,
+/// else the largest one. Deeper colors win among images of the same size.
+pub fn best_image(images: &[IconImage], size: u32) -> Option<&IconImage> {
+    images.iter().min_by_key(|image| {
+        let image_size = image.width.max(image.height);
+        let distance = if image_size >= size {
+            (false, image_size - size)
+        } else {
+            (true, size - image_size)
+        };
+        (distance, Reverse(image.bit_count))
+    })
+}
+
+fn is_png(bytes: &[u8]) -> bool {
+    bytes.starts_with(&PNG_SIGNATURE)
+}
+
+fn dimension(size: u8) -> u32 {
+    match size {
+        0 => 256,
+        size => size as u32,
+    }
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(
+        bytes.get(offset..offset + 2)?.try_into().ok()?,
+    ))
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        bytes.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// BITMAPINFOHEADER of an icon image; only the size and bit count are read.
+    fn dib(bit_count: u16) -> Vec<u8> {
+        let mut data = vec![0; 40];
+        data[0] = 40;
+        data[14..16].copy_from_slice(&bit_count.to_le_bytes());
+        data
+    }
+
+    /// PNG signature and the start of the IHDR chunk.
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let mut data = PNG_SIGNATURE.to_vec();
+        data.extend_from_slice(&13u32.to_be_bytes());
+        data.extend_from_slice(b"IHDR");
+        data.extend_from_slice(&width.to_be_bytes());
+        data.extend_from_slice(&height.to_be_bytes());
+        data
+    }
+
+    /// ICO file with one entry per `(size, bit_count, data)`; a size of 0 means 256.
+    fn ico(images: &[(u8, u16, Vec<u8>)]) -> Vec<u8> {
+        let mut bytes = vec![0, 0, 1, 0];
+        bytes.extend_from_slice(&(images.len() as u16).to_le_bytes());
+        let mut offset = ICONDIR_SIZE + images.len() * ICONDIRENTRY_SIZE;
+        for (size, bit_count, data) in images {
+            bytes.extend_from_slice(&[*size, *size, 0, 0, 1, 0]);
+            bytes.extend_from_slice(&bit_count.to_le_bytes());
+            bytes.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            bytes.extend_from_slice(&(offset as u32).to_le_bytes());
+            offset += data.len();
+        }
+        for (_, _, data) in images {
+            bytes.extend_from_slice(data);
+        }
+        bytes
+    }
+
+    fn sizes(images: &[IconImage]) -> Vec<(u32, u16)> {
+        images
+            .iter()
+            .map(|image| (image.width, image.bit_count))
+            .collect()
+    }
+
+    #[test]
+    fn parse_ico_reads_every_entry() {
+        let bytes = ico(&[
+            (16, 32, dib(32)),
+            (32, 8, dib(8)),
+            (32, 32, dib(32)),
+            (0, 0, png(256, 256)),
+        ]);
+        let images = parse_ico(&bytes).unwrap();
+        assert_eq!(sizes(&images), [(16, 32), (32, 8), (32, 32), (256, 32)]);
+        assert_eq!(images[1].data, dib(8));
+        assert!(images[3].is_png());
+        assert_eq!(images[3].height, 256);
+    }
+
+    #[test]
+    fn best_image_prefers_the_smallest_large_enough_and_deepest() {
+        let images = parse_ico(&ico(&[
+            (16, 32, dib(32)),
+            (32, 8, dib(8)),
+            (32, 32, dib(32)),
+            (48, 32, dib(32)),
+        ]))
+        .unwrap();
+        let best = |size| best_image(&images, size).map(|image| (image.width, image.bit_count));
+        assert_eq!(best(16), Some((16, 32)));
+        assert_eq!(best(20), Some((32, 32)));
+        assert_eq!(best(32), Some((32, 32)));
+        // Nothing is large enough, so the largest one is scaled up
+        assert_eq!(best(64), Some((48, 32)));
+        assert_eq!(best_image(&[], 32), None);
+    }
+
+    #[test]
+    fn parse_ico_takes_png_sizes_from_the_image() {
+        // The directory claims 16 pixels but the PNG is 24
+        let images = parse_ico(&ico(&[(16, 0, png(24, 24))])).unwrap();
+        assert_eq!(sizes(&images), [(24, 32)]);
+    }
+
+    #[test]
+    fn parse_ico_accepts_zero_images() {
+        assert_eq!(parse_ico(&ico(&[])), Some(Vec::new()));
+    }
+
+    #[test]
+    fn parse_ico_rejects_truncated_headers() {
+        let bytes = ico(&[(16, 32, dib(32))]);
+        assert_eq!(parse_ico(&bytes[..4]), None);
+        // The directory ends inside the entry
+        assert_eq!(parse_ico(&bytes[..ICONDIR_SIZE + 10]), None);
+        // Cursor files have type 2
+        let mut cursor = bytes.clone();
+        cursor[2] = 2;
+        assert_eq!(parse_ico(&cursor), None);
+        // A BITMAPINFOHEADER that is too small
+        assert_eq!(parse_ico(&ico(&[(16, 32, vec![12, 0, 0, 0])])), None);
+        // A PNG that ends before its IHDR sizes
+        assert_eq!(
+            parse_ico(&ico(&[(16, 32, png(16, 16)[..18].to_vec())])),
+            None
+        );
+    }
+
+    #[test]
+    fn parse_ico_rejects_images_outside_the_file() {
+        let bytes = ico(&[(16, 32, dib(32))]);
+        assert_eq!(parse_ico(&bytes[..bytes.len() - 1]), None);
+
+        let size_field = ICONDIR_SIZE + 8;
+        let mut too_long = bytes.clone();
+        too_long[size_field..size_field + 4].copy_from_slice(&41u32.to_le_bytes());
+        assert_eq!(parse_ico(&too_long), None);
+
+        let offset_field = ICONDIR_SIZE + 12;
+        let mut past_end = bytes.clone();
+        past_end[offset_field..offset_field + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(parse_ico(&past_end), None);
+    }
+}
@@ -102,6 +102,13 @@ impl Keyboard {
     }
 }
 
+impl Default for Keyboard {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
 pub struct Event {
     event_type: EventType,
     code: u16,
@@ -137,7 +144,7 @@ impl Default for Event {
         }
     }
 }
-#[derive(PartialEq)]
+#[derive(Clone, Copy, PartialEq, Debug)]
 pub enum EventType {
     Press,
     Release,
@@ -0,0 +1,31 @@
+#[macro_use]
+mod error;
+pub use error::Win32Error;
+mod win32_common;
+mod drop_target;
+pub mod dpi;
+pub mod event;
+pub mod wide_string;
+pub mod window;
+pub mod window_builder;
+pub mod keyboard;
+pub mod hit_test;
+pub mod mouse;
+pub mod mouse_filter;
+pub mod message_hook;
+pub mod graphics;
+pub mod clipboard;
+pub mod dialog;
+pub mod icon;
+pub mod monitor;
+pub mod placement;
+pub mod timing;
+pub mod application;
+pub mod app;
+pub type Result<T> = core::result::Result<T, Win32Error>;
+
+pub use app::{run, App, BackgroundBehavior};
+pub use application::Application;
+pub use event::WindowEvent;
+pub use window::{Window, WindowId};
+pub use window_builder::WindowBuilder;
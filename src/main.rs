@@ -1,17 +1,48 @@
-#[macro_use]
-mod error;
-use error::Win32Error;
-mod win32_common;
-mod window;
-mod keyboard;
-mod mouse;
-mod graphics;
-use graphics::Graphics;
-mod app;
-use app::App;
-pub type Result<T> = core::result::Result<T, Win32Error>;
+use std::time::Duration;
+
+use win3d::dialog::{Dialogs, MessageBoxButtons, MessageBoxIcon, NativeDialogs};
+use win3d::{App, Application, Result, Window, WindowBuilder, WindowEvent};
+use windows::Win32::UI::Input::KeyboardAndMouse::VK_MENU;
+
+#[derive(Default)]
+struct Demo {
+    previous_time: f32,
+    time: f32,
+}
+
+impl Application for Demo {
+    fn fixed_update(&mut self, _app: &mut App, step: Duration) -> Result<()> {
+        self.previous_time = self.time;
+        self.time += step.as_secs_f32();
+        Ok(())
+    }
+
+    fn on_event(&mut self, window: &mut Window, event: &WindowEvent) -> Result<()> {
+        if let WindowEvent::Key(key) = event {
+            if key.is_press() && key.get_code() == VK_MENU {
+                NativeDialogs::new().message_box(
+                    Some(window.id()),
+                    "ALT Key Pressed!",
+                    "Message Received!",
+                    MessageBoxButtons::Ok,
+                    MessageBoxIcon::Information,
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    fn render(&mut self, window: &mut Window, alpha: f32) -> Result<()> {
+        let time = self.previous_time + (self.time - self.previous_time) * alpha;
+        let c = f32::sin(time) / 2.0 + 0.5;
+        if let Some(mut gfx) = window.graphics_mut() {
+            gfx.clear_buffer(c / 1.2, 1.0 - c / 1.5,  1.0 - c /1.2);
+            gfx.draw_test_triangle()?;
+        }
+        Ok(())
+    }
+}
 
 fn main() -> Result<()> {
-    let mut app = App::new();
-    app.run() 
+    win3d::run(WindowBuilder::new().title("win3d").size(800, 600), Demo::default())
 }
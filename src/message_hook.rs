@@ -0,0 +1,181 @@
+use std::cell::{Cell, RefCell};
+use std::mem;
+
+use windows::core::Error;
+use windows::Win32::Foundation::{HWND, LPARAM, LRESULT, WPARAM};
+use windows::Win32::UI::WindowsAndMessaging::RegisterWindowMessageW;
+
+use crate::error::Win32Error;
+use crate::wide_string::WideCString;
+use crate::window::WindowId;
+pub type Result<T> = core::result::Result<T, Win32Error>;
+
+/// A message as the window procedure received it.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct RawMessage {
+    pub window: WindowId,
+    pub message: u32,
+    pub wparam: WPARAM,
+    pub lparam: LPARAM,
+}
+
+/// What the window procedure does with a message after a hook saw it.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum HookResult {
+    /// Let later hooks and the window's own handling see the message.
+    Continue,
+    /// Stop handling the message and return the value from the window procedure.
+    Consume(LRESULT),
+}
+
+/// Identifies an installed hook so it can be removed again.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct HookId(u64);
+
+pub(crate) type Hook = Box<dyn FnMut(&RawMessage) -> HookResult>;
+
+/// Hooks of a window, called in the order they were added. Changed through `&self`, as
+/// the window procedure only shares the window state.
+#[derive(Default)]
+pub(crate) struct MessageHooks {
+    hooks: RefCell<Vec<(HookId, Hook)>>,
+    next_id: Cell<u64>,
+}
+
+impl MessageHooks {
+    pub(crate) fn add(&self, hook: Hook) -> HookId {
+        let id = HookId(self.next_id.get());
+        self.next_id.set(id.0 + 1);
+        self.hooks.borrow_mut().push((id, hook));
+        id
+    }
+
+    pub(crate) fn remove(&self, id: HookId) -> bool {
+        let mut hooks = self.hooks.borrow_mut();
+        let count = hooks.len();
+        hooks.retain(|(hook_id, _)| *hook_id != id);
+        hooks.len() != count
+    }
+
+    /// Passes the message to each hook until one consumes it.
+    ///
+    /// The hooks are moved out while they run, so messages sent from inside a hook,
+    /// e.g. by `SendMessageW`, reach the window without passing the hooks again. They
+    /// are put back afterwards, also when a hook panics.
+    pub(crate) fn call(
+        &self,
+        window_handle: HWND,
+        message: u32,
+        wparam: WPARAM,
+        lparam: LPARAM,
+    ) -> HookResult {
+        if self.hooks.borrow().is_empty() {
+            return HookResult::Continue;
+        }
+        let message = RawMessage {
+            window: WindowId::from_handle(window_handle),
+            message,
+            wparam,
+            lparam,
+        };
+        let mut running = RunningHooks {
+            hooks: &self.hooks,
+            taken: self.hooks.take(),
+        };
+        running
+            .taken
+            .iter_mut()
+            .map(|(_, hook)| hook(&message))
+            .find(|result| *result != HookResult::Continue)
+            .unwrap_or(HookResult::Continue)
+    }
+}
+
+/// Hooks moved out of `MessageHooks` while they run, put back when dropped.
+struct RunningHooks<'a> {
+    hooks: &'a RefCell<Vec<(HookId, Hook)>>,
+    taken: Vec<(HookId, Hook)>,
+}
+
+impl Drop for RunningHooks<'_> {
+    fn drop(&mut self) {
+        let mut hooks = self.hooks.borrow_mut();
+        // Hooks added in the meantime come after the ones that were running
+        let added = mem::replace(&mut *hooks, mem::take(&mut self.taken));
+        hooks.extend(added);
+    }
+}
+
+/// Message number of a custom message shared between applications, as returned by
+/// `RegisterWindowMessageW`. Every process registering `name` gets the same number.
+pub fn register_message(name: &str) -> Result<u32> {
+    let name = WideCString::try_from(name)?;
+    match unsafe { RegisterWindowMessageW(&name) } {
+        0 => Err(win_error!(Error::from_win32())),
+        message => Ok(message),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::panic::{self, AssertUnwindSafe};
+    use std::rc::Rc;
+
+    use super::*;
+
+    fn call(hooks: &MessageHooks) -> HookResult {
+        hooks.call(0, 0x0400, 1, 2)
+    }
+
+    #[test]
+    fn hooks_run_in_order_until_one_consumes() {
+        let hooks = MessageHooks::default();
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        for (name, result) in [
+            ("first", HookResult::Continue),
+            ("second", HookResult::Consume(7)),
+            ("third", HookResult::Continue),
+        ] {
+            let seen = seen.clone();
+            hooks.add(Box::new(move |message| {
+                assert_eq!(
+                    (message.message, message.wparam, message.lparam),
+                    (0x0400, 1, 2)
+                );
+                seen.borrow_mut().push(name);
+                result
+            }));
+        }
+        assert_eq!(call(&hooks), HookResult::Consume(7));
+        assert_eq!(*seen.borrow(), ["first", "second"]);
+    }
+
+    #[test]
+    fn removed_hooks_are_not_called() {
+        let hooks = MessageHooks::default();
+        let id = hooks.add(Box::new(|_| HookResult::Consume(1)));
+        assert!(hooks.remove(id));
+        assert!(!hooks.remove(id));
+        assert_eq!(call(&hooks), HookResult::Continue);
+    }
+
+    #[test]
+    fn hooks_are_kept_when_one_panics() {
+        let hooks = MessageHooks::default();
+        let calls = Rc::new(Cell::new(0));
+        let counter = calls.clone();
+        hooks.add(Box::new(move |_| {
+            counter.set(counter.get() + 1);
+            HookResult::Continue
+        }));
+        let panicking = hooks.add(Box::new(|_| panic!("hook failed")));
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| call(&hooks)));
+        assert!(result.is_err());
+        assert_eq!(calls.get(), 1);
+
+        assert!(hooks.remove(panicking));
+        assert_eq!(call(&hooks), HookResult::Continue);
+        assert_eq!(calls.get(), 2);
+    }
+}
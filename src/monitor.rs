@@ -0,0 +1,403 @@
+use std::fmt;
+use std::ptr;
+
+use windows::Win32::Foundation::{BOOL, LPARAM, PWSTR, RECT};
+use windows::Win32::Graphics::Dxgi::{CreateDXGIFactory, IDXGIFactory, IDXGIOutput};
+use windows::Win32::Graphics::Gdi::{
+    EnumDisplayMonitors, EnumDisplaySettingsW, GetMonitorInfoW, DEVMODEW, ENUM_CURRENT_SETTINGS,
+    HDC, HMONITOR, MONITORINFOEXW,
+};
+use windows::Win32::UI::HiDpi::{GetDpiForMonitor, MDT_EFFECTIVE_DPI};
+use windows::Win32::UI::WindowsAndMessaging::MONITORINFOF_PRIMARY;
+
+use crate::dpi::{scale_factor_for_dpi, DEFAULT_DPI};
+use crate::error::Win32Error;
+use crate::graphics::{output_display_modes, DisplayMode};
+use crate::win32_common::settings_entries;
+pub type Result<T> = core::result::Result<T, Win32Error>;
+
+/// Rectangle in screen coordinates, `right` and `bottom` exclusive.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Rect {
+    pub left: i32,
+    pub top: i32,
+    pub right: i32,
+    pub bottom: i32,
+}
+
+impl Rect {
+    pub fn width(&self) -> i32 {
+        self.right - self.left
+    }
+
+    pub fn height(&self) -> i32 {
+        self.bottom - self.top
+    }
+
+    /// Area of the intersection with `other`.
+    pub fn overlap(&self, other: &Rect) -> i64 {
+        let width = self.right.min(other.right) as i64 - self.left.max(other.left) as i64;
+        let height = self.bottom.min(other.bottom) as i64 - self.top.max(other.top) as i64;
+        width.max(0) * height.max(0)
+    }
+
+    /// Parses the `Display` form, `left,top,right,bottom`.
+    pub fn parse(text: &str) -> Option<Rect> {
+        let mut values = text.split(',').map(|value| value.trim().parse().ok());
+        let rect = Rect {
+            left: values.next()??,
+            top: values.next()??,
+            right: values.next()??,
+            bottom: values.next()??,
+        };
+        match values.next() {
+            Some(_) => None,
+            None => Some(rect),
+        }
+    }
+}
+
+impl fmt::Display for Rect {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{},{},{},{}",
+            self.left, self.top, self.right, self.bottom
+        )
+    }
+}
+
+impl From<RECT> for Rect {
+    fn from(rect: RECT) -> Self {
+        Rect {
+            left: rect.left,
+            top: rect.top,
+            right: rect.right,
+            bottom: rect.bottom,
+        }
+    }
+}
+
+/// A connected monitor and the display modes it supports.
+///
+/// Fullscreen modes use the monitor the window is on, so pick one by moving the window
+/// there with `Window::move_to_monitor` first. Monitors are identified across runs by
+/// `name`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Monitor {
+    /// GDI device name, e.g. `\\.\DISPLAY1`.
+    pub name: String,
+    /// Whole monitor, in screen coordinates.
+    pub bounds: Rect,
+    /// Monitor without the taskbar and docked toolbars.
+    pub work_area: Rect,
+    pub dpi: u32,
+    /// Current refresh rate in hertz; 0 or 1 stand for the hardware default.
+    pub refresh_rate: u32,
+    pub primary: bool,
+    /// Modes of the DXGI output showing this monitor, in the swap chain format. Empty if
+    /// no output drives it, e.g. for some mirrored or virtual displays.
+    pub display_modes: Vec<DisplayMode>,
+}
+
+impl Monitor {
+    pub fn scale_factor(&self) -> f32 {
+        scale_factor_for_dpi(self.dpi)
+    }
+
+    /// One `key=value` pair per line, with a `display_mode` line per mode.
+    pub fn to_settings(&self) -> String {
+        let mut settings = format!(
+            "name={}\nbounds={}\nwork_area={}\ndpi={}\nrefresh_rate={}\nprimary={}\n",
+            self.name, self.bounds, self.work_area, self.dpi, self.refresh_rate, self.primary
+        );
+        for mode in &self.display_modes {
+            settings += &format!("display_mode={}\n", mode);
+        }
+        settings
+    }
+
+    /// Parses the output of `to_settings`, ignoring unknown keys. Returns `None` if the
+    /// name or an area is missing, or any value is malformed.
+    pub fn from_settings(settings: &str) -> Option<Monitor> {
+        let (mut name, mut bounds, mut work_area) = (None, None, None);
+        let mut monitor = Monitor {
+            name: String::new(),
+            bounds: Rect::from(RECT::default()),
+            work_area: Rect::from(RECT::default()),
+            dpi: DEFAULT_DPI,
+            refresh_rate: 0,
+            primary: false,
+            display_modes: Vec::new(),
+        };
+        for (key, value) in settings_entries(settings) {
+            match key {
+                "name" => name = Some(value.to_string()),
+                "bounds" => bounds = Some(Rect::parse(value)?),
+                "work_area" => work_area = Some(Rect::parse(value)?),
+                "dpi" => monitor.dpi = value.parse().ok()?,
+                "refresh_rate" => monitor.refresh_rate = value.parse().ok()?,
+                "primary" => monitor.primary = value.parse().ok()?,
+                "display_mode" => monitor.display_modes.push(DisplayMode::parse(value)?),
+                _ => {}
+            }
+        }
+        monitor.name = name?;
+        monitor.bounds = bounds?;
+        monitor.work_area = work_area?;
+        Some(monitor)
+    }
+}
+
+/// All monitors that are part of the desktop.
+pub fn monitors() -> Result<Vec<Monitor>> {
+    let mut handles: Vec<HMONITOR> = Vec::new();
+    unsafe {
+        EnumDisplayMonitors(
+            None,
+            ptr::null(),
+            Some(collect_monitor),
+            &mut handles as *mut Vec<HMONITOR> as LPARAM,
+        )
+        .ok()
+        .map_err(|e| win_error!(e))?;
+    }
+    let outputs = outputs()?;
+    handles
+        .into_iter()
+        .map(|handle| monitor_from_handle(handle, &outputs))
+        .collect()
+}
+
+pub fn primary_monitor() -> Result<Option<Monitor>> {
+    Ok(monitors()?.into_iter().find(|monitor| monitor.primary))
+}
+
+pub(crate) fn monitor(handle: HMONITOR) -> Result<Monitor> {
+    monitor_from_handle(handle, &outputs()?)
+}
+
+/// Name, bounds and work area of a monitor, all that `GetMonitorInfoW` reports.
+pub(crate) struct MonitorArea {
+    pub(crate) name: String,
+    pub(crate) bounds: Rect,
+    pub(crate) work_area: Rect,
+}
+
+/// Area of the monitor `handle`. Unlike `monitor` it needs no DXGI enumeration, which
+/// can fail, e.g. with `DXGI_ERROR_NOT_CURRENTLY_AVAILABLE` under Remote Desktop.
+pub(crate) fn monitor_area(handle: HMONITOR) -> Result<MonitorArea> {
+    let info = monitor_info(handle)?;
+    Ok(MonitorArea {
+        name: device_name(&info),
+        bounds: info.monitorInfo.rcMonitor.into(),
+        work_area: info.monitorInfo.rcWork.into(),
+    })
+}
+
+unsafe extern "system" fn collect_monitor(
+    handle: HMONITOR,
+    _hdc: HDC,
+    _rect: *mut RECT,
+    handles: LPARAM,
+) -> BOOL {
+    (*(handles as *mut Vec<HMONITOR>)).push(handle);
+    BOOL(1)
+}
+
+/// DXGI outputs of all adapters with the monitor each one drives.
+fn outputs() -> Result<Vec<(HMONITOR, IDXGIOutput)>> {
+    let mut outputs = Vec::new();
+    unsafe {
+        let factory: IDXGIFactory = CreateDXGIFactory().map_err(|e| win_error!(e))?;
+        // Both enumerations end with DXGI_ERROR_NOT_FOUND
+        let mut adapter_index = 0;
+        while let Ok(adapter) = factory.EnumAdapters(adapter_index) {
+            let mut output_index = 0;
+            while let Ok(output) = adapter.EnumOutputs(output_index) {
+                let desc = output.GetDesc().map_err(|e| win_error!(e))?;
+                outputs.push((desc.Monitor, output));
+                output_index += 1;
+            }
+            adapter_index += 1;
+        }
+    }
+    Ok(outputs)
+}
+
+fn monitor_info(handle: HMONITOR) -> Result<MONITORINFOEXW> {
+    let mut info = MONITORINFOEXW::default();
+    info.monitorInfo.cbSize = std::mem::size_of::<MONITORINFOEXW>() as u32;
+    unsafe {
+        GetMonitorInfoW(handle, &mut info.monitorInfo)
+            .ok()
+            .map_err(|e| win_error!(e))?;
+    }
+    Ok(info)
+}
+
+fn device_name(info: &MONITORINFOEXW) -> String {
+    let length = info
+        .szDevice
+        .iter()
+        .position(|unit| *unit == 0)
+        .unwrap_or(info.szDevice.len());
+    String::from_utf16_lossy(&info.szDevice[..length])
+}
+
+fn monitor_from_handle(handle: HMONITOR, outputs: &[(HMONITOR, IDXGIOutput)]) -> Result<Monitor> {
+    unsafe {
+        let mut info = monitor_info(handle)?;
+
+        // Effective DPI is the same for both axes
+        let (mut dpi, mut dpi_y) = (DEFAULT_DPI, DEFAULT_DPI);
+        if GetDpiForMonitor(handle, MDT_EFFECTIVE_DPI, &mut dpi, &mut dpi_y).is_err() {
+            dpi = DEFAULT_DPI;
+        }
+
+        let mut mode = DEVMODEW {
+            dmSize: std::mem::size_of::<DEVMODEW>() as u16,
+            ..Default::default()
+        };
+        let refresh_rate = if EnumDisplaySettingsW(
+            PWSTR(info.szDevice.as_mut_ptr()),
+            ENUM_CURRENT_SETTINGS,
+            &mut mode,
+        )
+        .as_bool()
+        {
+            mode.dmDisplayFrequency
+        } else {
+            0
+        };
+
+        let display_modes = match outputs.iter().find(|(monitor, _)| *monitor == handle) {
+            Some((_, output)) => output_display_modes(output)?,
+            None => Vec::new(),
+        };
+
+        Ok(Monitor {
+            name: device_name(&info),
+            bounds: info.monitorInfo.rcMonitor.into(),
+            work_area: info.monitorInfo.rcWork.into(),
+            dpi,
+            refresh_rate,
+            primary: info.monitorInfo.dwFlags & MONITORINFOF_PRIMARY != 0,
+            display_modes,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rect(left: i32, top: i32, right: i32, bottom: i32) -> Rect {
+        Rect {
+            left,
+            top,
+            right,
+            bottom,
+        }
+    }
+
+    fn mode(width: u32, height: u32, numerator: u32, denominator: u32) -> DisplayMode {
+        DisplayMode {
+            width,
+            height,
+            refresh_rate_numerator: numerator,
+            refresh_rate_denominator: denominator,
+        }
+    }
+
+    fn monitor(display_modes: Vec<DisplayMode>) -> Monitor {
+        Monitor {
+            name: r"\\.\DISPLAY2".into(),
+            bounds: rect(-1920, -200, 0, 880),
+            work_area: rect(-1920, -200, 0, 840),
+            dpi: 144,
+            refresh_rate: 144,
+            primary: false,
+            display_modes,
+        }
+    }
+
+    #[test]
+    fn rect_round_trip() {
+        let rect = rect(-1920, -200, 0, 880);
+        assert_eq!(rect.to_string(), "-1920,-200,0,880");
+        assert_eq!(Rect::parse(&rect.to_string()), Some(rect));
+        assert_eq!(Rect::parse(" -1920 , -200,0, 880 "), Some(rect));
+    }
+
+    #[test]
+    fn rect_parse_rejects_malformed_input() {
+        for text in [
+            "",
+            "1,2,3",
+            "1,2,3,4,",
+            "1,2,3,4,5",
+            "1,2,x,4",
+            "1,,3,4",
+            "1.5,2,3,4",
+            "2147483648,0,0,0",
+        ] {
+            assert_eq!(Rect::parse(text), None, "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn settings_round_trip() {
+        let monitor = monitor(vec![mode(1920, 1080, 60000, 1001), mode(1280, 720, 60, 1)]);
+        assert_eq!(
+            Monitor::from_settings(&monitor.to_settings()),
+            Some(monitor)
+        );
+    }
+
+    #[test]
+    fn settings_round_trip_without_display_modes() {
+        let monitor = monitor(Vec::new());
+        let settings = monitor.to_settings();
+        assert!(!settings.contains("display_mode"));
+        assert_eq!(Monitor::from_settings(&settings), Some(monitor));
+    }
+
+    #[test]
+    fn from_settings_ignores_unknown_keys_and_defaults_optional_ones() {
+        let settings = "name=A\nbounds=0,0,800,600\nwork_area=0,0,800,560\ncolor=blue\nno pair\n";
+        let monitor = Monitor::from_settings(settings).unwrap();
+        assert_eq!(monitor.name, "A");
+        assert_eq!(monitor.work_area, rect(0, 0, 800, 560));
+        assert_eq!(monitor.dpi, DEFAULT_DPI);
+        assert_eq!(monitor.refresh_rate, 0);
+        assert!(!monitor.primary);
+        assert!(monitor.display_modes.is_empty());
+    }
+
+    #[test]
+    fn from_settings_rejects_missing_and_malformed_values() {
+        let valid = monitor(vec![mode(1920, 1080, 60, 1)]).to_settings();
+        let replace = |key: &str, line: &str| {
+            valid
+                .lines()
+                .map(|l| if l.starts_with(key) { line } else { l })
+                .collect::<Vec<_>>()
+                .join("\n")
+        };
+        for settings in [
+            replace("name=", ""),
+            replace("bounds=", ""),
+            replace("work_area=", ""),
+            replace("bounds=", "bounds=0,0,800,600,"),
+            replace("work_area=", "work_area=0,0,800"),
+            replace("dpi=", "dpi=high"),
+            replace("refresh_rate=", "refresh_rate=-60"),
+            replace("primary=", "primary=yes"),
+            replace("display_mode=", "display_mode="),
+            replace("display_mode=", "display_mode=1920x1080@60"),
+        ] {
+            assert_eq!(Monitor::from_settings(&settings), None, "{}", settings);
+        }
+    }
+}
@@ -1,7 +1,11 @@
 use std::collections::VecDeque;
 
+use crate::dpi::scale_factor_for_dpi;
+use crate::hit_test::{HitTester, HoverChange, RegionId, Shape};
+use crate::mouse_filter::MouseFilter;
+
 static BUFFER_SIZE: usize = 16;
-static WHEEL_DELTA: usize = 120;
+static WHEEL_DELTA: i32 = 120;
 
 pub struct Mouse {
     x: isize,
@@ -9,7 +13,14 @@ pub struct Mouse {
     left_is_pressed: bool,
     right_is_pressed: bool,
     is_in_window: bool,
-    wheel_delta_carry: usize,
+    wheel_delta_carry: i32,
+    client_width: i32,
+    client_height: i32,
+    scale_factor: f32,
+    delta_x: f32,
+    delta_y: f32,
+    filters: Vec<Box<dyn MouseFilter>>,
+    regions: HitTester,
     buffer: VecDeque<Event>,
 }
 
@@ -22,6 +33,13 @@ impl Mouse {
             right_is_pressed: false,
             is_in_window: false,
             wheel_delta_carry: 0,
+            client_width: 0,
+            client_height: 0,
+            scale_factor: 1.0,
+            delta_x: 0.0,
+            delta_y: 0.0,
+            filters: Vec::new(),
+            regions: HitTester::new(),
             buffer: VecDeque::<Event>::with_capacity(BUFFER_SIZE as usize),
         }
     }
@@ -50,6 +68,56 @@ impl Mouse {
         self.is_in_window
     }
 
+    /// Whether the physical pixel position lies inside the client area.
+    pub fn is_in_client_area(&self, x: isize, y: isize) -> bool {
+        x >= 0 && y >= 0 && x < self.client_width as isize && y < self.client_height as isize
+    }
+
+    /// Ratio between physical pixels and logical (96 DPI) units.
+    pub fn scale_factor(&self) -> f32 {
+        self.scale_factor
+    }
+
+    /// Appends a filter to the chain that processes movement deltas.
+    pub fn add_filter(&mut self, filter: Box<dyn MouseFilter>) {
+        self.filters.push(filter);
+    }
+
+    pub fn clear_filters(&mut self) {
+        self.filters.clear();
+    }
+
+    /// Registers a named hit-test region in client coordinates. Regions with a higher
+    /// `z_order` are hit first and receive hover and click events.
+    pub fn add_region(&mut self, name: &str, shape: Shape, z_order: i32) -> RegionId {
+        let id = self.regions.add(name, shape, z_order);
+        // The new region may now be under a stationary cursor
+        if self.is_in_window {
+            self.update_hover();
+        }
+        id
+    }
+
+    pub fn remove_region(&mut self, id: RegionId) {
+        let change = self.regions.remove(id);
+        self.on_hover_change(change);
+        if self.is_in_window {
+            self.update_hover();
+        }
+    }
+
+    pub fn region_name(&self, id: RegionId) -> Option<&str> {
+        self.regions.name(id)
+    }
+
+    pub fn find_region(&self, name: &str) -> Option<RegionId> {
+        self.regions.find(name)
+    }
+
+    pub fn hovered_region(&self) -> Option<RegionId> {
+        self.regions.hovered()
+    }
+
     pub fn read(&mut self) -> Option<Event> {
         self.buffer.pop_front()
     }
@@ -63,59 +131,109 @@ impl Mouse {
     }
 
     pub fn on_mouse_move(&mut self, new_x: isize, new_y: isize) {
+        // The first move after entering the window has no meaningful previous position
+        let (dx, dy) = if self.is_in_window {
+            ((new_x - self.x) as f32, (new_y - self.y) as f32)
+        } else {
+            (0.0, 0.0)
+        };
+        (self.delta_x, self.delta_y) = self.filter_delta(dx, dy);
         self.x = new_x;
         self.y = new_y;
 
-        self.buffer.push_back(Event::new(EventType::Move, &self));
+        self.buffer.push_back(Event::new(EventType::Move, self));
+        self.trim_buffer();
+
+        // Only move events carry a delta
+        self.delta_x = 0.0;
+        self.delta_y = 0.0;
+
+        self.update_hover();
+    }
+
+    fn update_hover(&mut self) {
+        let position = if self.is_in_client_area(self.x, self.y) {
+            Some((self.x, self.y))
+        } else {
+            None
+        };
+        let change = self.regions.update(position);
+        self.on_hover_change(change);
+    }
+
+    fn on_hover_change(&mut self, change: HoverChange) {
+        if let Some(id) = change.left {
+            self.buffer.push_back(Event::new(EventType::HoverLeave(id), self));
+        }
+        if let Some(id) = change.entered {
+            self.buffer.push_back(Event::new(EventType::HoverEnter(id), self));
+        }
         self.trim_buffer();
     }
 
+    fn on_click(&mut self) {
+        if let Some(id) = self.regions.hovered() {
+            self.buffer.push_back(Event::new(EventType::Click(id), self));
+            self.trim_buffer();
+        }
+    }
+
+    fn filter_delta(&mut self, dx: f32, dy: f32) -> (f32, f32) {
+        self.filters
+            .iter_mut()
+            .fold((dx, dy), |(dx, dy), filter| filter.apply(dx, dy))
+    }
+
     pub fn on_left_pressed(&mut self) {
         self.left_is_pressed = true;
 
-        self.buffer.push_back(Event::new(EventType::LPress, &self));
+        self.buffer.push_back(Event::new(EventType::LPress, self));
         self.trim_buffer();
+        self.on_click();
 
     }
 
     pub fn on_left_released(&mut self) {
         self.left_is_pressed = false;
 
-        self.buffer.push_back(Event::new(EventType::LRelease, &self));
+        self.buffer.push_back(Event::new(EventType::LRelease, self));
         self.trim_buffer();
     }
 
     pub fn on_right_pressed(&mut self) {
         self.right_is_pressed = true;
 
-        self.buffer.push_back(Event::new(EventType::RPress, &self));
+        self.buffer.push_back(Event::new(EventType::RPress, self));
         self.trim_buffer();
+        self.on_click();
     }
 
     pub fn on_right_released(&mut self) {
         self.right_is_pressed = false;
 
-        self.buffer.push_back(Event::new(EventType::RRelease, &self));
+        self.buffer.push_back(Event::new(EventType::RRelease, self));
         self.trim_buffer();
     }
 
     pub fn on_wheel_up(&mut self) {
-        self.buffer.push_back(Event::new(EventType::WheelUp, &self));
+        self.buffer.push_back(Event::new(EventType::WheelUp, self));
         self.trim_buffer();
     }
 
     pub fn on_wheel_down(&mut self) {
-        self.buffer.push_back(Event::new(EventType::WheelDown, &self));
+        self.buffer.push_back(Event::new(EventType::WheelDown, self));
         self.trim_buffer();
     }
 
-    pub fn on_wheel_delta(&mut self, x: isize, y: isize, delta: usize) {
+    pub fn on_wheel_delta(&mut self, x: isize, y: isize, delta: i32) {
+        self.x = x;
+        self.y = y;
         self.wheel_delta_carry += delta;
         while self.wheel_delta_carry >= WHEEL_DELTA {
             self.wheel_delta_carry -= WHEEL_DELTA;
             self.on_wheel_up();
         }
-        while self.wheel_delta_carry <= WHEEL_DELTA {
+        while self.wheel_delta_carry <= -WHEEL_DELTA {
             self.wheel_delta_carry += WHEEL_DELTA;
             self.on_wheel_down();
         }
@@ -123,26 +241,55 @@ impl Mouse {
 
     pub fn on_mouse_leave(&mut self) {
         self.is_in_window = false;
-        self.buffer.push_back(Event::new(EventType::Leave, &self));
+        for filter in self.filters.iter_mut() {
+            filter.reset();
+        }
+        let change = self.regions.update(None);
+        self.on_hover_change(change);
+        self.buffer.push_back(Event::new(EventType::Leave, self));
         self.trim_buffer();
     }
 
     pub fn on_mouse_enter(&mut self) {
         self.is_in_window = true;
-        self.buffer.push_back(Event::new(EventType::Enter, &self));
+        self.buffer.push_back(Event::new(EventType::Enter, self));
         self.trim_buffer();
     }
 
+    /// Client area size in physical pixels, used for bounds checks and NDC conversion.
+    pub fn on_client_resize(&mut self, width: i32, height: i32) {
+        self.client_width = width;
+        self.client_height = height;
+    }
+
+    /// DPI of the monitor the window is on, used to derive logical positions.
+    pub fn on_dpi_changed(&mut self, dpi: u32) {
+        self.scale_factor = scale_factor_for_dpi(dpi);
+    }
+
     pub fn trim_buffer(&mut self) {
         self.buffer.truncate(BUFFER_SIZE as usize)
     }
 
 }
 
+impl Default for Mouse {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
 pub struct Event {
     event_type: EventType,
     x: isize,
     y: isize,
+    logical_x: f32,
+    logical_y: f32,
+    ndc_x: f32,
+    ndc_y: f32,
+    delta_x: f32,
+    delta_y: f32,
     left_is_pressed: bool,
     right_is_pressed: bool,
 
@@ -154,6 +301,12 @@ impl Default for Event {
             event_type: EventType::Invalid,
             x: 0,
             y: 0,
+            logical_x: 0.0,
+            logical_y: 0.0,
+            ndc_x: 0.0,
+            ndc_y: 0.0,
+            delta_x: 0.0,
+            delta_y: 0.0,
             left_is_pressed: false,
             right_is_pressed: false,
         }
@@ -162,10 +315,17 @@ impl Default for Event {
 
 impl Event {
     pub fn new(event_type: EventType, parent: &Mouse) -> Event {
+        let (ndc_x, ndc_y) = to_ndc(parent.x, parent.y, parent.client_width, parent.client_height);
         Event{
-            event_type: event_type,
+            event_type,
             x: parent.x,
             y: parent.y,
+            logical_x: parent.x as f32 / parent.scale_factor,
+            logical_y: parent.y as f32 / parent.scale_factor,
+            ndc_x,
+            ndc_y,
+            delta_x: parent.delta_x,
+            delta_y: parent.delta_y,
             left_is_pressed: parent.left_is_pressed,
             right_is_pressed: parent.right_is_pressed,
 
@@ -188,6 +348,23 @@ impl Event {
         self.y
     }
 
+    /// Position in DPI-independent units (physical pixels divided by the scale factor).
+    pub fn get_logical_pos(&self) -> (f32, f32) {
+        (self.logical_x, self.logical_y)
+    }
+
+    /// Position in normalized device coordinates: -1 at the left/bottom edge of the
+    /// client area, +1 at the right/top edge, y pointing up like the D3D clip space.
+    pub fn get_ndc(&self) -> (f32, f32) {
+        (self.ndc_x, self.ndc_y)
+    }
+
+    /// Movement since the previous move event after the mouse filters were applied.
+    /// Zero for all other event types.
+    pub fn get_delta(&self) -> (f32, f32) {
+        (self.delta_x, self.delta_y)
+    }
+
     pub fn left_is_pressed(&self) -> bool {
         self.left_is_pressed
     }
@@ -198,7 +375,17 @@ impl Event {
 
 }
 
-#[derive(Clone, Copy, PartialEq)]
+// Maps the center of pixel (x, y) into normalized device coordinates.
+fn to_ndc(x: isize, y: isize, width: i32, height: i32) -> (f32, f32) {
+    if width <= 0 || height <= 0 {
+        return (0.0, 0.0);
+    }
+    let ndc_x = (x as f32 + 0.5) / width as f32 * 2.0 - 1.0;
+    let ndc_y = 1.0 - (y as f32 + 0.5) / height as f32 * 2.0;
+    (ndc_x, ndc_y)
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
 pub enum EventType {
     LPress,
     LRelease,
@@ -209,5 +396,45 @@ pub enum EventType {
     Move,
     Enter,
     Leave,
+    /// The cursor moved onto the topmost region under it.
+    HoverEnter(RegionId),
+    HoverLeave(RegionId),
+    /// A button was pressed over the hovered region; check the button state on the event.
+    Click(RegionId),
     Invalid,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mouse_filter::{Acceleration, Curve, MovingAverage};
+
+    fn move_deltas(mouse: &mut Mouse) -> Vec<(f32, f32)> {
+        std::iter::from_fn(|| mouse.read())
+            .filter(|event| event.get_type() == EventType::Move)
+            .map(|event| event.get_delta())
+            .collect()
+    }
+
+    #[test]
+    fn filters_run_in_order_and_reset_on_leave() {
+        let mut mouse = Mouse::new();
+        mouse.add_filter(Box::new(Acceleration::new(Curve::Power {
+            sensitivity: 1.0,
+            exponent: 2.0,
+        })));
+        mouse.add_filter(Box::new(MovingAverage::new(2)));
+
+        mouse.on_mouse_enter();
+        mouse.on_mouse_move(10, 0);
+        mouse.on_mouse_move(10, 0);
+        // Averaging first would give 100 and 25
+        assert_eq!(move_deltas(&mut mouse), [(100.0, 0.0), (50.0, 0.0)]);
+
+        mouse.on_mouse_leave();
+        mouse.on_mouse_enter();
+        mouse.on_mouse_move(12, 0);
+        // The average no longer includes the samples from before leaving
+        assert_eq!(move_deltas(&mut mouse), [(4.0, 0.0)]);
+    }
+}
@@ -0,0 +1,208 @@
+use std::collections::VecDeque;
+
+/// A processing stage applied to raw mouse deltas before they are buffered as events.
+///
+/// Filters are chained in the order they were added to the `Mouse`, each one receiving
+/// the output of the previous stage.
+pub trait MouseFilter {
+    fn apply(&mut self, dx: f32, dy: f32) -> (f32, f32);
+
+    /// Forget any accumulated history, e.g. when the mouse leaves the window.
+    fn reset(&mut self) {}
+}
+
+/// Exponential moving average: `out = factor * in + (1 - factor) * previous_out`.
+///
+/// A factor of 1.0 passes deltas through unchanged, smaller values smooth more.
+pub struct ExponentialSmoothing {
+    factor: f32,
+    previous: (f32, f32),
+}
+
+impl ExponentialSmoothing {
+    /// Factors outside `[0, 1]` are clamped to it.
+    ///
+    /// # Panics
+    ///
+    /// If `factor` is NaN or infinite.
+    pub fn new(factor: f32) -> ExponentialSmoothing {
+        assert!(factor.is_finite(), "smoothing factor must be finite");
+        ExponentialSmoothing {
+            factor: factor.clamp(0.0, 1.0),
+            previous: (0.0, 0.0),
+        }
+    }
+}
+
+impl MouseFilter for ExponentialSmoothing {
+    fn apply(&mut self, dx: f32, dy: f32) -> (f32, f32) {
+        let x = self.factor * dx + (1.0 - self.factor) * self.previous.0;
+        let y = self.factor * dy + (1.0 - self.factor) * self.previous.1;
+        self.previous = (x, y);
+        self.previous
+    }
+
+    fn reset(&mut self) {
+        self.previous = (0.0, 0.0);
+    }
+}
+
+/// Averages the last `sample_count` deltas.
+pub struct MovingAverage {
+    sample_count: usize,
+    samples: VecDeque<(f32, f32)>,
+}
+
+impl MovingAverage {
+    pub fn new(sample_count: usize) -> MovingAverage {
+        let sample_count = sample_count.max(1);
+        MovingAverage {
+            sample_count,
+            samples: VecDeque::with_capacity(sample_count),
+        }
+    }
+}
+
+impl MouseFilter for MovingAverage {
+    fn apply(&mut self, dx: f32, dy: f32) -> (f32, f32) {
+        if self.samples.len() == self.sample_count {
+            self.samples.pop_front();
+        }
+        self.samples.push_back((dx, dy));
+
+        let n = self.samples.len() as f32;
+        let (sum_x, sum_y) = self
+            .samples
+            .iter()
+            .fold((0.0, 0.0), |(sx, sy), (x, y)| (sx + x, sy + y));
+        (sum_x / n, sum_y / n)
+    }
+
+    fn reset(&mut self) {
+        self.samples.clear();
+    }
+}
+
+/// Maps the speed of a movement (delta length in pixels) to an output speed.
+pub enum Curve {
+    /// `out = sensitivity * speed`
+    Linear { sensitivity: f32 },
+    /// `out = sensitivity * speed^exponent`, accelerating for exponents above 1.0.
+    Power { sensitivity: f32, exponent: f32 },
+    /// Any user supplied mapping from input speed to output speed.
+    Custom(Box<dyn Fn(f32) -> f32>),
+}
+
+impl Curve {
+    pub fn evaluate(&self, speed: f32) -> f32 {
+        match self {
+            Curve::Linear { sensitivity } => sensitivity * speed,
+            Curve::Power {
+                sensitivity,
+                exponent,
+            } => sensitivity * speed.powf(*exponent),
+            Curve::Custom(curve) => curve(speed),
+        }
+    }
+}
+
+/// Scales deltas by a sensitivity/acceleration curve, preserving their direction.
+pub struct Acceleration {
+    curve: Curve,
+}
+
+impl Acceleration {
+    pub fn new(curve: Curve) -> Acceleration {
+        Acceleration { curve }
+    }
+}
+
+impl MouseFilter for Acceleration {
+    fn apply(&mut self, dx: f32, dy: f32) -> (f32, f32) {
+        let speed = dx.hypot(dy);
+        if speed == 0.0 {
+            return (0.0, 0.0);
+        }
+        let gain = self.curve.evaluate(speed) / speed;
+        (dx * gain, dy * gain)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(filter: &mut dyn MouseFilter, deltas: &[(f32, f32)]) -> Vec<(f32, f32)> {
+        deltas
+            .iter()
+            .map(|(dx, dy)| filter.apply(*dx, *dy))
+            .collect()
+    }
+
+    #[test]
+    fn exponential_smoothing_blends_with_the_previous_output() {
+        let mut filter = ExponentialSmoothing::new(0.5);
+        let output = run(&mut filter, &[(4.0, 8.0), (4.0, 8.0), (0.0, 0.0)]);
+        assert_eq!(output, [(2.0, 4.0), (3.0, 6.0), (1.5, 3.0)]);
+
+        filter.reset();
+        assert_eq!(filter.apply(4.0, 8.0), (2.0, 4.0));
+    }
+
+    #[test]
+    fn exponential_smoothing_clamps_the_factor() {
+        let mut filter = ExponentialSmoothing::new(2.0);
+        assert_eq!(
+            run(&mut filter, &[(4.0, 8.0), (1.0, 2.0)]),
+            [(4.0, 8.0), (1.0, 2.0)]
+        );
+        let mut filter = ExponentialSmoothing::new(-1.0);
+        assert_eq!(filter.apply(4.0, 8.0), (0.0, 0.0));
+    }
+
+    #[test]
+    #[should_panic(expected = "must be finite")]
+    fn exponential_smoothing_rejects_nan() {
+        ExponentialSmoothing::new(f32::NAN);
+    }
+
+    #[test]
+    #[should_panic(expected = "must be finite")]
+    fn exponential_smoothing_rejects_infinity() {
+        ExponentialSmoothing::new(f32::INFINITY);
+    }
+
+    #[test]
+    fn moving_average_covers_the_last_samples() {
+        let mut filter = MovingAverage::new(2);
+        let output = run(&mut filter, &[(2.0, 4.0), (4.0, 0.0), (6.0, 2.0)]);
+        assert_eq!(output, [(2.0, 4.0), (3.0, 2.0), (5.0, 1.0)]);
+
+        filter.reset();
+        assert_eq!(filter.apply(8.0, 8.0), (8.0, 8.0));
+    }
+
+    #[test]
+    fn acceleration_scales_the_speed_and_keeps_the_direction() {
+        let mut linear = Acceleration::new(Curve::Linear { sensitivity: 2.0 });
+        assert_eq!(
+            run(&mut linear, &[(3.0, 4.0), (0.0, 0.0)]),
+            [(6.0, 8.0), (0.0, 0.0)]
+        );
+
+        let mut power = Acceleration::new(Curve::Power {
+            sensitivity: 1.0,
+            exponent: 2.0,
+        });
+        assert_eq!(
+            run(&mut power, &[(3.0, 4.0), (-1.0, 0.0)]),
+            [(15.0, 20.0), (-1.0, 0.0)]
+        );
+
+        let mut custom = Acceleration::new(Curve::Custom(Box::new(|speed| speed.min(1.0))));
+        assert_eq!(
+            run(&mut custom, &[(0.0, 10.0), (0.0, -0.5)]),
+            [(0.0, 1.0), (0.0, -0.5)]
+        );
+    }
+}
@@ -0,0 +1,220 @@
+use std::fs;
+use std::path::Path;
+
+use windows::Win32::Foundation::E_FAIL;
+
+use crate::error::Win32Error;
+use crate::monitor::{Monitor, Rect};
+use crate::win32_common::settings_entries;
+pub type Result<T> = core::result::Result<T, Win32Error>;
+
+/// Position, size and state of a window, saved when it closes and restored when it
+/// opens again.
+///
+/// The file format is one `key=value` pair per line; unknown keys are ignored so the
+/// format can grow without breaking older files.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct WindowPlacement {
+    /// Outer window rectangle in screen coordinates while neither maximized nor
+    /// fullscreen.
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+    pub maximized: bool,
+    /// Borderless fullscreen; exclusive fullscreen is not restored.
+    pub fullscreen: bool,
+    /// Device name of the monitor the window was on, e.g. `\\.\DISPLAY1`.
+    pub monitor: String,
+}
+
+impl WindowPlacement {
+    pub fn to_settings(&self) -> String {
+        format!(
+            "x={}\ny={}\nwidth={}\nheight={}\nmaximized={}\nfullscreen={}\nmonitor={}\n",
+            self.x, self.y, self.width, self.height, self.maximized, self.fullscreen, self.monitor
+        )
+    }
+
+    /// Parses the output of `to_settings`. Returns `None` if the position or a positive
+    /// size is missing or malformed, or if the window would end beyond the coordinate
+    /// range.
+    pub fn from_settings(settings: &str) -> Option<WindowPlacement> {
+        let (mut x, mut y, mut width, mut height) = (None, None, None, None);
+        let mut placement = WindowPlacement {
+            x: 0,
+            y: 0,
+            width: 0,
+            height: 0,
+            maximized: false,
+            fullscreen: false,
+            monitor: String::new(),
+        };
+        for (key, value) in settings_entries(settings) {
+            match key {
+                "x" => x = Some(value.parse().ok()?),
+                "y" => y = Some(value.parse().ok()?),
+                "width" => width = Some(value.parse().ok()?),
+                "height" => height = Some(value.parse().ok()?),
+                "maximized" => placement.maximized = value.parse().ok()?,
+                "fullscreen" => placement.fullscreen = value.parse().ok()?,
+                "monitor" => placement.monitor = value.into(),
+                _ => {}
+            }
+        }
+        placement.x = x?;
+        placement.y = y?;
+        placement.width = width.filter(|width| *width > 0)?;
+        placement.height = height.filter(|height| *height > 0)?;
+        placement.x.checked_add(placement.width)?;
+        placement.y.checked_add(placement.height)?;
+        Some(placement)
+    }
+
+    /// Reads a placement file; a missing or damaged file yields `None`.
+    pub fn load(path: &Path) -> Option<WindowPlacement> {
+        WindowPlacement::from_settings(&fs::read_to_string(path).ok()?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        fs::write(path, self.to_settings())
+            .map_err(|e| Win32Error::with_message(E_FAIL, e.to_string()))
+    }
+
+    /// Moves and shrinks the placement so it lies within the work area of one of
+    /// `monitors`.
+    ///
+    /// That is the monitor the window overlaps most, or, if it is entirely off-screen
+    /// because the layout changed, the monitor it was saved on or else the primary one.
+    pub fn fit_to_monitors(&self, monitors: &[Monitor]) -> WindowPlacement {
+        let rect = Rect {
+            left: self.x,
+            top: self.y,
+            right: self.x.saturating_add(self.width),
+            bottom: self.y.saturating_add(self.height),
+        };
+        let target = monitors
+            .iter()
+            .map(|monitor| (rect.overlap(&monitor.work_area), monitor))
+            .filter(|(overlap, _)| *overlap > 0)
+            .max_by_key(|(overlap, _)| *overlap)
+            .map(|(_, monitor)| monitor)
+            .or_else(|| monitors.iter().find(|monitor| monitor.name == self.monitor))
+            .or_else(|| monitors.iter().find(|monitor| monitor.primary))
+            .or_else(|| monitors.first());
+        let monitor = match target {
+            Some(monitor) => monitor,
+            None => return self.clone(),
+        };
+
+        let area = monitor.work_area;
+        let width = self.width.min(area.width());
+        let height = self.height.min(area.height());
+        WindowPlacement {
+            x: self.x.clamp(area.left, area.right - width),
+            y: self.y.clamp(area.top, area.bottom - height),
+            width,
+            height,
+            maximized: self.maximized,
+            fullscreen: self.fullscreen,
+            monitor: monitor.name.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn monitor(name: &str, work_area: Rect, primary: bool) -> Monitor {
+        Monitor {
+            name: name.into(),
+            bounds: work_area,
+            work_area,
+            dpi: 96,
+            refresh_rate: 60,
+            primary,
+            display_modes: Vec::new(),
+        }
+    }
+
+    fn placement(x: i32, y: i32, width: i32, height: i32) -> WindowPlacement {
+        WindowPlacement {
+            x,
+            y,
+            width,
+            height,
+            maximized: false,
+            fullscreen: false,
+            monitor: r"\\.\DISPLAY1".into(),
+        }
+    }
+
+    #[test]
+    fn settings_round_trip() {
+        let placement = placement(-10, 20, 800, 600);
+        assert_eq!(
+            WindowPlacement::from_settings(&placement.to_settings()),
+            Some(placement)
+        );
+    }
+
+    #[test]
+    fn from_settings_rejects_windows_beyond_the_coordinate_range() {
+        let settings = "x=2147483647\ny=0\nwidth=800\nheight=600\n";
+        assert_eq!(WindowPlacement::from_settings(settings), None);
+        let settings = "x=0\ny=2147483600\nwidth=800\nheight=600\n";
+        assert_eq!(WindowPlacement::from_settings(settings), None);
+    }
+
+    #[test]
+    fn fit_to_monitors_moves_far_off_windows_onto_the_saved_monitor() {
+        let monitors = [
+            monitor(
+                r"\\.\DISPLAY2",
+                Rect {
+                    left: 1920,
+                    top: 0,
+                    right: 3840,
+                    bottom: 1040,
+                },
+                false,
+            ),
+            monitor(
+                r"\\.\DISPLAY1",
+                Rect {
+                    left: 0,
+                    top: 0,
+                    right: 1920,
+                    bottom: 1040,
+                },
+                true,
+            ),
+        ];
+        let fitted = placement(i32::MAX, i32::MIN, 800, 600).fit_to_monitors(&monitors);
+        assert_eq!(
+            (fitted.x, fitted.y, fitted.width, fitted.height),
+            (1120, 0, 800, 600)
+        );
+        assert_eq!(fitted.monitor, r"\\.\DISPLAY1");
+    }
+
+    #[test]
+    fn fit_to_monitors_shrinks_to_the_work_area() {
+        let monitors = [monitor(
+            r"\\.\DISPLAY1",
+            Rect {
+                left: 0,
+                top: 0,
+                right: 1280,
+                bottom: 720,
+            },
+            true,
+        )];
+        let fitted = placement(100, 100, 2000, 600).fit_to_monitors(&monitors);
+        assert_eq!(
+            (fitted.x, fitted.y, fitted.width, fitted.height),
+            (0, 100, 1280, 600)
+        );
+    }
+}
@@ -0,0 +1,492 @@
+use std::cell::Cell;
+use std::collections::VecDeque;
+use std::fmt;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use windows::Win32::Media::{timeBeginPeriod, timeEndPeriod};
+
+/// Remaining wait that `precise_sleep` spends yielding instead of sleeping, as the
+/// scheduler may wake a thread up to a timer period late.
+const SPIN_THRESHOLD: Duration = Duration::from_millis(2);
+/// Longest frame time `FrameLimiter` waits for, so tiny target rates stay in range.
+pub const MAX_FRAME_TIME: Duration = Duration::from_secs(3600);
+
+/// Monotonic time source for the application loop.
+///
+/// The loop only ever looks at differences between two readings, so the origin is
+/// arbitrary. Swapping in a `ManualClock` makes the loop timing fully deterministic.
+pub trait Clock {
+    fn now(&self) -> Duration;
+
+    /// Blocks until `duration` has passed on this clock.
+    fn sleep(&self, duration: Duration);
+}
+
+/// Wall-clock time since the clock was created.
+pub struct SystemClock {
+    start: Instant,
+}
+
+impl SystemClock {
+    pub fn new() -> SystemClock {
+        SystemClock {
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Default for SystemClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        precise_sleep(duration);
+    }
+}
+
+/// Clock that only moves when told to, for tests and replays.
+pub struct ManualClock {
+    now: Cell<Duration>,
+}
+
+impl ManualClock {
+    pub fn new() -> ManualClock {
+        ManualClock {
+            now: Cell::new(Duration::ZERO),
+        }
+    }
+
+    pub fn advance(&self, by: Duration) {
+        self.now.set(self.now.get() + by);
+    }
+
+    pub fn set(&self, now: Duration) {
+        self.now.set(now);
+    }
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Duration {
+        self.now.get()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        self.advance(duration);
+    }
+}
+
+/// Result of advancing a `FixedTimestep` to the current time.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct FixedSteps {
+    /// Number of fixed updates to run this frame.
+    pub count: u32,
+    /// Fraction of a step left in the accumulator, in `[0, 1)`. Renderers blend the
+    /// previous and current simulation state with it.
+    pub alpha: f32,
+}
+
+/// Accumulator that turns variable frame times into a whole number of fixed steps.
+///
+/// At most `max_steps` are run per frame. When the simulation falls further behind
+/// (a breakpoint, a dragged window) the excess time is dropped instead of making the
+/// next frames even slower, which would never catch up again.
+pub struct FixedTimestep {
+    step: Duration,
+    max_steps: u32,
+    accumulator: Duration,
+    last: Option<Duration>,
+}
+
+impl FixedTimestep {
+    pub fn new(step: Duration, max_steps: u32) -> FixedTimestep {
+        assert!(!step.is_zero(), "fixed timestep must be positive");
+        FixedTimestep {
+            step,
+            max_steps: max_steps.max(1),
+            accumulator: Duration::ZERO,
+            last: None,
+        }
+    }
+
+    /// Timestep for `rate` updates per second.
+    ///
+    /// # Panics
+    ///
+    /// If `rate` is NaN or not positive, or so high that a step rounds to zero.
+    pub fn from_rate(rate: f64, max_steps: u32) -> FixedTimestep {
+        let step = Duration::try_from_secs_f64(1.0 / rate)
+            .unwrap_or_else(|_| panic!("fixed update rate must be positive, got {}", rate));
+        FixedTimestep::new(step, max_steps)
+    }
+
+    pub fn step(&self) -> Duration {
+        self.step
+    }
+
+    pub fn max_steps(&self) -> u32 {
+        self.max_steps
+    }
+
+    /// Accounts for the time passed since the previous call. The first call only
+    /// establishes the starting point.
+    pub fn advance(&mut self, now: Duration) -> FixedSteps {
+        if let Some(last) = self.last {
+            self.accumulator += now.saturating_sub(last);
+        }
+        self.last = Some(now);
+
+        let mut count = 0;
+        while self.accumulator >= self.step && count < self.max_steps {
+            self.accumulator -= self.step;
+            count += 1;
+        }
+        if self.accumulator >= self.step {
+            let remainder = self.accumulator.as_nanos() % self.step.as_nanos();
+            self.accumulator = Duration::from_nanos(remainder as u64);
+        }
+
+        FixedSteps {
+            count,
+            alpha: (self.accumulator.as_secs_f64() / self.step.as_secs_f64()) as f32,
+        }
+    }
+
+    /// Forgets the accumulated time, e.g. after the loop was paused.
+    pub fn reset(&mut self) {
+        self.accumulator = Duration::ZERO;
+        self.last = None;
+    }
+
+    /// Resets and starts counting from `now`, so the time before it, e.g. spent paused,
+    /// is never caught up on.
+    pub fn restart(&mut self, now: Duration) {
+        self.reset();
+        self.advance(now);
+    }
+}
+
+/// Sleeps for `duration` with sub-millisecond accuracy: the OS sleep covers most of the
+/// wait and the remainder is spent yielding the thread until the deadline.
+pub fn precise_sleep(duration: Duration) {
+    let deadline = Instant::now() + duration;
+    if duration > SPIN_THRESHOLD {
+        thread::sleep(duration - SPIN_THRESHOLD);
+    }
+    while Instant::now() < deadline {
+        thread::yield_now();
+    }
+}
+
+/// Raises the system timer resolution to 1 ms while alive, so sleeps wake up on time.
+pub(crate) struct TimerResolution;
+
+impl TimerResolution {
+    pub(crate) fn new() -> TimerResolution {
+        unsafe {
+            timeBeginPeriod(1);
+        }
+        TimerResolution
+    }
+}
+
+impl Drop for TimerResolution {
+    fn drop(&mut self) {
+        unsafe {
+            timeEndPeriod(1);
+        }
+    }
+}
+
+/// Caps the frame rate by telling the loop how long to wait before each frame.
+///
+/// Frames are scheduled on a fixed grid, so an occasional late wake-up is made up by
+/// the next frame. When a frame takes longer than the target the grid restarts from
+/// the current time instead of rushing the following frames.
+pub struct FrameLimiter {
+    frame_time: Option<Duration>,
+    next_frame: Option<Duration>,
+}
+
+impl FrameLimiter {
+    /// `None` leaves the frame rate uncapped, so only vsync (if any) limits it.
+    pub fn new(target_fps: Option<f64>) -> FrameLimiter {
+        let mut limiter = FrameLimiter {
+            frame_time: None,
+            next_frame: None,
+        };
+        limiter.set_target_fps(target_fps);
+        limiter
+    }
+
+    pub fn target_fps(&self) -> Option<f64> {
+        self.frame_time.map(|frame_time| 1.0 / frame_time.as_secs_f64())
+    }
+
+    /// Rates that are not positive or NaN remove the cap. Rates below one frame per
+    /// `MAX_FRAME_TIME` wait that long.
+    pub fn set_target_fps(&mut self, target_fps: Option<f64>) {
+        self.frame_time = target_fps
+            .filter(|fps| !fps.is_nan() && *fps > 0.0)
+            .map(|fps| {
+                Duration::try_from_secs_f64(1.0 / fps)
+                    .map_or(MAX_FRAME_TIME, |frame_time| frame_time.min(MAX_FRAME_TIME))
+            })
+            .filter(|frame_time| !frame_time.is_zero());
+        self.next_frame = None;
+    }
+
+    /// Time to wait at `now` before starting the next frame.
+    pub fn wait_time(&mut self, now: Duration) -> Duration {
+        let frame_time = match self.frame_time {
+            Some(frame_time) => frame_time,
+            None => return Duration::ZERO,
+        };
+        match self.next_frame {
+            Some(next_frame) if next_frame > now => {
+                self.next_frame = Some(next_frame + frame_time);
+                next_frame - now
+            }
+            _ => {
+                self.next_frame = Some(now + frame_time);
+                Duration::ZERO
+            }
+        }
+    }
+}
+
+/// Rolling statistics over the most recent frame times.
+pub struct FrameStats {
+    capacity: usize,
+    frame_times: VecDeque<Duration>,
+    frame_count: u64,
+}
+
+impl FrameStats {
+    /// Keeps the last `capacity` frames.
+    pub fn new(capacity: usize) -> FrameStats {
+        let capacity = capacity.max(1);
+        FrameStats {
+            capacity,
+            frame_times: VecDeque::with_capacity(capacity),
+            frame_count: 0,
+        }
+    }
+
+    pub fn record(&mut self, frame_time: Duration) {
+        if self.frame_times.len() == self.capacity {
+            self.frame_times.pop_front();
+        }
+        self.frame_times.push_back(frame_time);
+        self.frame_count += 1;
+    }
+
+    pub fn clear(&mut self) {
+        self.frame_times.clear();
+    }
+
+    /// Frames recorded since creation, including those no longer in the window.
+    pub fn frame_count(&self) -> u64 {
+        self.frame_count
+    }
+
+    pub fn average(&self) -> Option<Duration> {
+        if self.frame_times.is_empty() {
+            return None;
+        }
+        let total: Duration = self.frame_times.iter().sum();
+        Some(total / self.frame_times.len() as u32)
+    }
+
+    pub fn min(&self) -> Option<Duration> {
+        self.frame_times.iter().min().copied()
+    }
+
+    pub fn max(&self) -> Option<Duration> {
+        self.frame_times.iter().max().copied()
+    }
+
+    /// Nearest-rank percentile, `percent` in `[0, 100]`. `percentile(99.0)` is the
+    /// frame time that 99% of the frames did not exceed.
+    pub fn percentile(&self, percent: f32) -> Option<Duration> {
+        if self.frame_times.is_empty() {
+            return None;
+        }
+        let mut sorted: Vec<Duration> = self.frame_times.iter().copied().collect();
+        sorted.sort_unstable();
+        let rank = (percent.clamp(0.0, 100.0) / 100.0 * sorted.len() as f32).ceil() as usize;
+        Some(sorted[rank.max(1) - 1])
+    }
+
+    /// Frames per second over the window, derived from the average frame time.
+    pub fn fps(&self) -> Option<f64> {
+        self.average()
+            .filter(|average| !average.is_zero())
+            .map(|average| 1.0 / average.as_secs_f64())
+    }
+}
+
+impl fmt::Display for FrameStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let millis = |duration: Option<Duration>| {
+            duration.map_or(0.0, |duration| duration.as_secs_f64() * 1000.0)
+        };
+        write!(
+            f,
+            "{:.1} fps, frame time avg {:.2} ms, min {:.2} ms, max {:.2} ms, p99 {:.2} ms",
+            self.fps().unwrap_or(0.0),
+            millis(self.average()),
+            millis(self.min()),
+            millis(self.max()),
+            millis(self.percentile(99.0)),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use super::*;
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    fn steps(count: u32, alpha: f32) -> FixedSteps {
+        FixedSteps { count, alpha }
+    }
+
+    #[test]
+    fn fixed_timestep_counts_whole_steps() {
+        let mut timestep = FixedTimestep::new(ms(10), 5);
+        assert_eq!(timestep.advance(ms(100)), steps(0, 0.0));
+        assert_eq!(timestep.advance(ms(125)), steps(2, 0.5));
+        // The half step left over carries into the next frame
+        assert_eq!(timestep.advance(ms(130)), steps(1, 0.0));
+        assert_eq!(timestep.advance(ms(132)), steps(0, 0.2));
+    }
+
+    #[test]
+    fn fixed_timestep_drops_time_beyond_max_steps() {
+        let mut timestep = FixedTimestep::new(ms(10), 3);
+        timestep.advance(ms(0));
+        assert_eq!(timestep.advance(ms(1005)), steps(3, 0.5));
+        assert_eq!(timestep.advance(ms(1010)), steps(1, 0.0));
+    }
+
+    #[test]
+    fn fixed_timestep_restart_skips_the_paused_time() {
+        let clock = Rc::new(ManualClock::new());
+        let shared: Rc<dyn Clock> = clock.clone();
+        let mut timestep = FixedTimestep::new(ms(10), 5);
+        timestep.restart(shared.now());
+        clock.advance(ms(15));
+        assert_eq!(timestep.advance(shared.now()), steps(1, 0.5));
+
+        // Paused for a second, then resumed
+        clock.advance(ms(1000));
+        timestep.restart(shared.now());
+        clock.advance(ms(10));
+        assert_eq!(timestep.advance(shared.now()), steps(1, 0.0));
+    }
+
+    #[test]
+    fn fixed_timestep_from_rate() {
+        assert_eq!(FixedTimestep::from_rate(4.0, 5).step(), ms(250));
+    }
+
+    #[test]
+    #[should_panic(expected = "must be positive")]
+    fn fixed_timestep_from_rate_rejects_zero() {
+        FixedTimestep::from_rate(0.0, 5);
+    }
+
+    #[test]
+    #[should_panic(expected = "must be positive")]
+    fn fixed_timestep_from_rate_rejects_nan() {
+        FixedTimestep::from_rate(f64::NAN, 5);
+    }
+
+    #[test]
+    #[should_panic(expected = "must be positive")]
+    fn fixed_timestep_from_rate_rejects_rates_beyond_a_nanosecond() {
+        FixedTimestep::from_rate(1e12, 5);
+    }
+
+    #[test]
+    fn frame_limiter_schedules_frames_on_a_grid() {
+        let mut limiter = FrameLimiter::new(Some(4.0));
+        assert_eq!(limiter.wait_time(ms(0)), Duration::ZERO);
+        assert_eq!(limiter.wait_time(ms(100)), ms(150));
+        // Due at 250 but woken up late, the next frame is still due at 500
+        assert_eq!(limiter.wait_time(ms(270)), ms(230));
+        assert_eq!(limiter.wait_time(ms(800)), Duration::ZERO);
+    }
+
+    #[test]
+    fn frame_limiter_restarts_the_grid_after_a_late_frame() {
+        let mut limiter = FrameLimiter::new(Some(4.0));
+        assert_eq!(limiter.wait_time(ms(0)), Duration::ZERO);
+        assert_eq!(limiter.wait_time(ms(900)), Duration::ZERO);
+        assert_eq!(limiter.wait_time(ms(1000)), ms(150));
+    }
+
+    #[test]
+    fn frame_limiter_without_a_cap_never_waits() {
+        for fps in [
+            None,
+            Some(0.0),
+            Some(-1.0),
+            Some(f64::NAN),
+            Some(f64::INFINITY),
+        ] {
+            let mut limiter = FrameLimiter::new(fps);
+            assert_eq!(limiter.target_fps(), None);
+            assert_eq!(limiter.wait_time(ms(0)), Duration::ZERO);
+            assert_eq!(limiter.wait_time(ms(1)), Duration::ZERO);
+        }
+    }
+
+    #[test]
+    fn frame_limiter_caps_tiny_rates() {
+        let mut limiter = FrameLimiter::new(Some(1e-20));
+        assert_eq!(limiter.wait_time(ms(0)), Duration::ZERO);
+        assert_eq!(limiter.wait_time(ms(0)), MAX_FRAME_TIME);
+    }
+
+    #[test]
+    fn frame_stats_summarize_the_window() {
+        let mut stats = FrameStats::new(4);
+        assert_eq!(stats.average(), None);
+        assert_eq!(stats.percentile(50.0), None);
+        for millis in [40, 10, 20, 30, 40] {
+            stats.record(ms(millis));
+        }
+        // The first 40 ms frame fell out of the window
+        assert_eq!(stats.frame_count(), 5);
+        assert_eq!(stats.average(), Some(ms(25)));
+        assert_eq!(stats.min(), Some(ms(10)));
+        assert_eq!(stats.max(), Some(ms(40)));
+        assert_eq!(stats.fps(), Some(40.0));
+        assert_eq!(stats.percentile(0.0), Some(ms(10)));
+        assert_eq!(stats.percentile(25.0), Some(ms(10)));
+        assert_eq!(stats.percentile(50.0), Some(ms(20)));
+        assert_eq!(stats.percentile(51.0), Some(ms(30)));
+        assert_eq!(stats.percentile(99.0), Some(ms(40)));
+        assert_eq!(stats.percentile(150.0), Some(ms(40)));
+    }
+}
@@ -0,0 +1,295 @@
+use std::error;
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::marker::PhantomData;
+use std::path::Path;
+use std::str::FromStr;
+use std::string::FromUtf16Error;
+
+use windows::core::{IntoParam, Param};
+use windows::Win32::Foundation::PWSTR;
+
+use crate::error::Win32Error;
+use crate::win32_common::os_string_from_wide;
+
+/// Owned, nul-terminated UTF-16 string for passing text to the OS.
+///
+/// The pointer handed to a call comes from `as_pcwstr`, whose lifetime is tied to the
+/// string, so the buffer cannot be freed while the call still uses it.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct WideCString {
+    // Always ends with the only nul
+    units: Vec<u16>,
+}
+
+/// Text passed to `WideCString` contained a nul, which would cut it short.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct InteriorNulError {
+    position: usize,
+    units: Vec<u16>,
+}
+
+impl InteriorNulError {
+    /// Index of the first nul in UTF-16 code units.
+    pub fn nul_position(&self) -> usize {
+        self.position
+    }
+
+    /// The rejected code units.
+    pub fn into_vec(self) -> Vec<u16> {
+        self.units
+    }
+}
+
+impl fmt::Display for InteriorNulError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "nul character at UTF-16 position {}", self.position)
+    }
+}
+
+impl error::Error for InteriorNulError {}
+
+impl From<InteriorNulError> for Win32Error {
+    #[track_caller]
+    fn from(error: InteriorNulError) -> Self {
+        Win32Error::invalid_argument(error.to_string())
+    }
+}
+
+impl WideCString {
+    /// Appends the terminating nul to `units`, which must not contain one.
+    pub fn new(units: impl Into<Vec<u16>>) -> Result<WideCString, InteriorNulError> {
+        let mut units = units.into();
+        if let Some(position) = units.iter().position(|unit| *unit == 0) {
+            return Err(InteriorNulError { position, units });
+        }
+        units.push(0);
+        Ok(WideCString { units })
+    }
+
+    /// Copies the string at `ptr` up to its terminating nul.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must point to a readable, nul-terminated UTF-16 string.
+    pub unsafe fn from_ptr(ptr: *const u16) -> WideCString {
+        let mut length = 0;
+        while *ptr.add(length) != 0 {
+            length += 1;
+        }
+        WideCString {
+            units: std::slice::from_raw_parts(ptr, length + 1).to_vec(),
+        }
+    }
+
+    /// Borrowed pointer to the string, valid as long as the string is.
+    pub fn as_pcwstr(&self) -> PCWSTR<'_> {
+        PCWSTR {
+            ptr: self.units.as_ptr(),
+            owner: PhantomData,
+        }
+    }
+
+    /// Code units without the terminating nul.
+    pub fn as_slice(&self) -> &[u16] {
+        &self.units[..self.units.len() - 1]
+    }
+
+    pub fn as_slice_with_nul(&self) -> &[u16] {
+        &self.units
+    }
+
+    /// Length in UTF-16 code units, without the terminating nul.
+    pub fn len(&self) -> usize {
+        self.units.len() - 1
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Decodes the string, failing on unpaired surrogates.
+    pub fn to_string_checked(&self) -> Result<String, FromUtf16Error> {
+        String::from_utf16(self.as_slice())
+    }
+
+    /// Decodes the string, replacing unpaired surrogates with U+FFFD.
+    pub fn to_string_lossy(&self) -> String {
+        String::from_utf16_lossy(self.as_slice())
+    }
+
+    /// Converts to an `OsString`, which keeps unpaired surrogates on Windows.
+    pub fn to_os_string(&self) -> OsString {
+        os_string_from_wide(self.as_slice())
+    }
+}
+
+impl FromStr for WideCString {
+    type Err = InteriorNulError;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        WideCString::new(text.encode_utf16().collect::<Vec<u16>>())
+    }
+}
+
+impl TryFrom<&str> for WideCString {
+    type Error = InteriorNulError;
+
+    fn try_from(text: &str) -> Result<Self, Self::Error> {
+        text.parse()
+    }
+}
+
+impl TryFrom<String> for WideCString {
+    type Error = InteriorNulError;
+
+    fn try_from(text: String) -> Result<Self, Self::Error> {
+        text.parse()
+    }
+}
+
+impl TryFrom<&String> for WideCString {
+    type Error = InteriorNulError;
+
+    fn try_from(text: &String) -> Result<Self, Self::Error> {
+        text.parse()
+    }
+}
+
+impl TryFrom<&OsStr> for WideCString {
+    type Error = InteriorNulError;
+
+    #[cfg(windows)]
+    fn try_from(text: &OsStr) -> Result<Self, Self::Error> {
+        use std::os::windows::ffi::OsStrExt;
+        WideCString::new(text.encode_wide().collect::<Vec<u16>>())
+    }
+
+    #[cfg(not(windows))]
+    fn try_from(text: &OsStr) -> Result<Self, Self::Error> {
+        text.to_string_lossy().parse()
+    }
+}
+
+impl TryFrom<&Path> for WideCString {
+    type Error = InteriorNulError;
+
+    fn try_from(path: &Path) -> Result<Self, Self::Error> {
+        WideCString::try_from(path.as_os_str())
+    }
+}
+
+/// Pointer to a nul-terminated UTF-16 string borrowed from a `WideCString`.
+///
+/// Can be passed wherever the bindings take a string parameter. Structs that store the
+/// pointer take `as_pwstr`, which drops the lifetime, so the owner must then outlive
+/// every use of the struct.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct PCWSTR<'a> {
+    ptr: *const u16,
+    owner: PhantomData<&'a [u16]>,
+}
+
+impl<'a> PCWSTR<'a> {
+    pub fn as_ptr(&self) -> *const u16 {
+        self.ptr
+    }
+
+    /// The pointer in the type the bindings use; the OS does not write through it for
+    /// input strings.
+    pub fn as_pwstr(&self) -> PWSTR {
+        PWSTR(self.ptr as *mut u16)
+    }
+}
+
+impl<'a> IntoParam<'a, PWSTR> for PCWSTR<'a> {
+    fn into_param(self) -> Param<'a, PWSTR> {
+        // Owned is not freed when the call returns, the WideCString keeps the buffer
+        Param::Owned(self.as_pwstr())
+    }
+}
+
+impl<'a> IntoParam<'a, PWSTR> for &'a WideCString {
+    fn into_param(self) -> Param<'a, PWSTR> {
+        self.as_pcwstr().into_param()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use windows::Win32::Foundation::E_INVALIDARG;
+
+    use super::*;
+
+    fn wide(text: &str) -> Vec<u16> {
+        text.encode_utf16().collect()
+    }
+
+    #[test]
+    fn new_appends_the_terminator() {
+        let string = WideCString::new(wide("héllo")).unwrap();
+        assert_eq!(string.len(), 5);
+        assert!(!string.is_empty());
+        assert_eq!(string.as_slice(), wide("héllo"));
+        assert_eq!(string.as_slice_with_nul(), wide("héllo\0"));
+
+        let empty = WideCString::new(Vec::new()).unwrap();
+        assert!(empty.is_empty());
+        assert_eq!(empty.as_slice(), []);
+        assert_eq!(empty.as_slice_with_nul(), [0]);
+    }
+
+    #[test]
+    fn new_rejects_interior_nuls() {
+        let error = WideCString::new(wide("ab\0c\0")).unwrap_err();
+        assert_eq!(error.nul_position(), 2);
+        assert_eq!(error.to_string(), "nul character at UTF-16 position 2");
+        assert_eq!(error.into_vec(), wide("ab\0c\0"));
+
+        let error = WideCString::try_from("\0").unwrap_err();
+        assert_eq!(error.nul_position(), 0);
+        let error: Win32Error = error.into();
+        assert_eq!(error.code(), E_INVALIDARG);
+    }
+
+    #[test]
+    fn converts_from_strings_and_paths() {
+        let expected = WideCString::new(wide("dir/file \u{1F600}.txt")).unwrap();
+        let text = "dir/file \u{1F600}.txt";
+        assert_eq!(text.parse::<WideCString>().unwrap(), expected);
+        assert_eq!(WideCString::try_from(text).unwrap(), expected);
+        assert_eq!(WideCString::try_from(&text.to_string()).unwrap(), expected);
+        assert_eq!(WideCString::try_from(text.to_string()).unwrap(), expected);
+        assert_eq!(WideCString::try_from(OsStr::new(text)).unwrap(), expected);
+        assert_eq!(WideCString::try_from(Path::new(text)).unwrap(), expected);
+        assert_eq!(expected.to_os_string(), OsString::from(text));
+
+        assert!(WideCString::try_from(String::from("a\0")).is_err());
+        assert!(WideCString::try_from(Path::new("a\0")).is_err());
+    }
+
+    #[test]
+    fn from_ptr_copies_up_to_the_terminator() {
+        let string = WideCString::try_from("round trip").unwrap();
+        let copy = unsafe { WideCString::from_ptr(string.as_pcwstr().as_ptr()) };
+        assert_eq!(copy, string);
+
+        let buffer = wide("first\0second\0");
+        let copy = unsafe { WideCString::from_ptr(buffer.as_ptr()) };
+        assert_eq!(copy.to_string_lossy(), "first");
+
+        let empty = unsafe { WideCString::from_ptr([0u16].as_ptr()) };
+        assert!(empty.is_empty());
+    }
+
+    #[test]
+    fn unpaired_surrogates_fail_checked_decoding() {
+        let string = WideCString::new(vec![b'a' as u16, 0xD800, b'b' as u16]).unwrap();
+        assert!(string.to_string_checked().is_err());
+        assert_eq!(string.to_string_lossy(), "a\u{FFFD}b");
+
+        let string = WideCString::try_from("\u{1F600}").unwrap();
+        assert_eq!(string.len(), 2);
+        assert_eq!(string.to_string_checked().unwrap(), "\u{1F600}");
+    }
+}
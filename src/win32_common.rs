@@ -1,11 +1,90 @@
-pub trait ToWide {
-    fn to_wide(&self) -> Vec<u16>;
+/// Signed x-coordinate packed in the low word of an `LPARAM` (`GET_X_LPARAM`).
+///
+/// Coordinates left of or above the client area (e.g. while the mouse is captured)
+/// are negative, so the word must be sign-extended rather than masked.
+pub fn get_x_lparam(lparam: isize) -> i32 {
+    (lparam & 0xFFFF) as u16 as i16 as i32
 }
 
-impl ToWide for &str {
-    fn to_wide(&self) -> Vec<u16> {
-        let mut result: Vec<u16> = self.encode_utf16().collect();
-        result.push(0);
-        result
+/// Signed y-coordinate packed in the high word of an `LPARAM` (`GET_Y_LPARAM`).
+pub fn get_y_lparam(lparam: isize) -> i32 {
+    ((lparam >> 16) & 0xFFFF) as u16 as i16 as i32
+}
+
+/// Signed wheel rotation packed in the high word of a `WPARAM` (`GET_WHEEL_DELTA_WPARAM`).
+pub fn get_wheel_delta_wparam(wparam: usize) -> i32 {
+    ((wparam >> 16) & 0xFFFF) as u16 as i16 as i32
+}
+
+/// Converts UTF-16 from the OS to an `OsString`, keeping unpaired surrogates on Windows.
+#[cfg(windows)]
+pub fn os_string_from_wide(wide: &[u16]) -> std::ffi::OsString {
+    use std::os::windows::ffi::OsStringExt;
+    std::ffi::OsString::from_wide(wide)
+}
+
+#[cfg(not(windows))]
+pub fn os_string_from_wide(wide: &[u16]) -> std::ffi::OsString {
+    String::from_utf16_lossy(wide).into()
+}
+
+/// `key=value` pairs of a settings file, one per line, trimmed. Lines without `=` are
+/// skipped.
+pub fn settings_entries(settings: &str) -> impl Iterator<Item = (&str, &str)> {
+    settings
+        .lines()
+        .filter_map(|line| line.split_once('='))
+        .map(|(key, value)| (key.trim(), value.trim()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // `MAKELPARAM`: the low word in the low 16 bits, the high word above it
+    fn make_lparam(low: i16, high: i16) -> isize {
+        ((high as u16 as isize) << 16) | low as u16 as isize
+    }
+
+    #[test]
+    fn lparam_coordinates_are_sign_extended() {
+        for (x, y) in [
+            (0, 0),
+            (-1, -1),
+            (-5, 7),
+            (7, -5),
+            (i16::MIN, i16::MAX),
+            (i16::MAX, i16::MIN),
+        ] {
+            let lparam = make_lparam(x, y);
+            assert_eq!(get_x_lparam(lparam), x as i32, "{:#x}", lparam);
+            assert_eq!(get_y_lparam(lparam), y as i32, "{:#x}", lparam);
+        }
+    }
+
+    #[test]
+    fn lparam_bits_above_the_high_word_are_ignored() {
+        // The packed value sign-extended to the full width, as a negative `LPARAM`
+        let lparam = make_lparam(-300, -20) as i32 as isize;
+        assert!(lparam < 0);
+        assert_eq!((get_x_lparam(lparam), get_y_lparam(lparam)), (-300, -20));
+    }
+
+    #[test]
+    fn wheel_delta_is_sign_extended() {
+        const MK_CONTROL: usize = 0x0008;
+        for delta in [120, -120, -240, -1, i16::MIN] {
+            let wparam = make_lparam(MK_CONTROL as i16, delta) as usize;
+            assert_eq!(
+                get_wheel_delta_wparam(wparam),
+                delta as i32,
+                "{:#x}",
+                wparam
+            );
+        }
+        assert_eq!(
+            get_wheel_delta_wparam(make_lparam(0, -120) as i32 as usize),
+            -120
+        );
     }
 }
@@ -1,22 +1,56 @@
-use crate::win32_common::ToWide;
+use crate::win32_common::{get_wheel_delta_wparam, get_x_lparam, get_y_lparam};
+use std::any::Any;
+use std::cell::{Cell, Ref, RefCell, RefMut};
+use std::collections::VecDeque;
 use std::ffi::c_void;
-use windows::Win32::Foundation::{BOOL, HWND, LPARAM, LRESULT, PWSTR, RECT, WPARAM};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::sync::{Mutex, Once, PoisonError};
+use windows::core::Error;
+use windows::Win32::Foundation::{
+    BOOL, HINSTANCE, HWND, LPARAM, LRESULT, POINT, PWSTR, RECT, WPARAM,
+};
+use windows::Win32::Graphics::Dxgi::DXGI_ERROR_INVALID_CALL;
+use windows::Win32::Graphics::Gdi::{
+    GetMonitorInfoW, MonitorFromWindow, ScreenToClient, MONITORINFO, MONITOR_DEFAULTTONEAREST,
+};
+use windows::Win32::System::DataExchange::AddClipboardFormatListener;
 use windows::Win32::System::LibraryLoader::GetModuleHandleW;
-use windows::Win32::UI::Input::KeyboardAndMouse::{ReleaseCapture, SetCapture, VK_MENU, VK_SPACE};
+use windows::Win32::UI::HiDpi::{
+    AdjustWindowRectExForDpi, GetDpiForWindow, SetProcessDpiAwarenessContext,
+    DPI_AWARENESS_CONTEXT_PER_MONITOR_AWARE_V2,
+};
+use windows::Win32::UI::Input::KeyboardAndMouse::{ReleaseCapture, SetCapture, VK_RETURN};
 use windows::Win32::UI::WindowsAndMessaging::{
-    AdjustWindowRect, CreateWindowExW, DefWindowProcW, DestroyWindow, DispatchMessageW,
-    GetMessageW, GetWindowLongPtrW, LoadCursorW, MessageBoxW, PeekMessageW, PostQuitMessage,
-    RegisterClassW, SetWindowLongPtrW, TranslateMessage, CREATESTRUCTW, CS_HREDRAW, CS_VREDRAW,
-    CW_USEDEFAULT, GWLP_USERDATA, IDC_CROSS, MB_OK, MK_LBUTTON, MK_RBUTTON, MSG, PM_REMOVE,
-    WM_ACTIVATE, WM_CHAR, WM_DESTROY, WM_KEYDOWN, WM_KEYUP, WM_KILLFOCUS, WM_LBUTTONDOWN,
-    WM_LBUTTONUP, WM_MOUSEHWHEEL, WM_MOUSEMOVE, WM_NCCREATE, WM_QUIT, WM_RBUTTONDOWN, WM_RBUTTONUP,
-    WM_SYSKEYDOWN, WM_SYSKEYUP, WNDCLASSW, WS_CAPTION, WS_MINIMIZEBOX, WS_OVERLAPPEDWINDOW,
-    WS_SYSMENU, WS_VISIBLE,
+    AdjustWindowRectEx, CreateWindowExW, DefWindowProcW, DestroyWindow, GetClientRect,
+    GetWindowLongPtrW, GetWindowLongW, GetWindowPlacement, GetWindowRect, GetWindowTextLengthW,
+    GetWindowTextW, IsIconic, IsWindowVisible, IsZoomed, LoadCursorW, PostMessageW, RegisterClassW,
+    SendMessageW, SetWindowLongPtrW, SetWindowLongW, SetWindowPlacement, SetWindowPos,
+    SetWindowTextW, ShowWindow, CREATESTRUCTW, CS_HREDRAW, CS_VREDRAW, CW_USEDEFAULT,
+    GWLP_USERDATA, GWL_EXSTYLE, GWL_STYLE, HWND_TOP, ICON_BIG, ICON_SMALL, IDC_CROSS, MINMAXINFO,
+    SIZE_MINIMIZED, SWP_FRAMECHANGED, SWP_NOACTIVATE, SWP_NOMOVE, SWP_NOOWNERZORDER, SWP_NOSIZE,
+    SWP_NOZORDER, SW_HIDE, SW_MAXIMIZE, SW_MINIMIZE, SW_RESTORE, SW_SHOW, SW_SHOWMAXIMIZED,
+    WINDOWPLACEMENT, WM_CHAR, WM_CLIPBOARDUPDATE, WM_CLOSE, WM_DESTROY, WM_DPICHANGED,
+    WM_GETMINMAXINFO, WM_KEYDOWN, WM_KEYUP, WM_KILLFOCUS, WM_LBUTTONDOWN, WM_LBUTTONUP,
+    WM_MOUSEMOVE, WM_MOUSEWHEEL, WM_NCCREATE, WM_NCDESTROY, WM_RBUTTONDOWN, WM_RBUTTONUP,
+    WM_SETFOCUS, WM_SETICON, WM_SIZE, WM_SYSCHAR, WM_SYSKEYDOWN, WM_SYSKEYUP, WNDCLASSW,
+    WS_EX_TOPMOST, WS_MAXIMIZEBOX, WS_OVERLAPPEDWINDOW, WS_POPUP, WS_THICKFRAME, WS_VISIBLE,
 };
 
 use crate::keyboard::Keyboard;
+use crate::message_hook::{HookId, HookResult, MessageHooks, RawMessage};
 use crate::mouse::Mouse;
-use crate::graphics::Graphics;
+use crate::clipboard::{self, Image};
+use crate::drop_target::{self, DropRegistration};
+use crate::dpi::{scale_factor_for_dpi, LogicalSize, PhysicalSize};
+use crate::event::WindowEvent;
+use crate::graphics::{DisplayMode, Graphics};
+use crate::icon::Icon;
+use crate::monitor::{self, Monitor};
+use crate::placement::WindowPlacement;
+use crate::wide_string::WideCString;
+use crate::window_builder::WindowBuilder;
 
 // Dealing with errors
 //======================
@@ -26,258 +60,1378 @@ use crate::graphics::Graphics;
 use crate::error::Win32Error;
 pub type Result<T> = core::result::Result<T, Win32Error>;
 
+/// Identifies a window in events routed through the application.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct WindowId(HWND);
+
+impl WindowId {
+    pub(crate) fn from_handle(window_handle: HWND) -> WindowId {
+        WindowId(window_handle)
+    }
+
+    pub(crate) fn handle(&self) -> HWND {
+        self.0
+    }
+}
+
+const WINDOW_CLASS_NAME: &str = "win3d_window";
+static ENABLE_DPI_AWARENESS: Once = Once::new();
+// Only set once registration succeeded, so a failed attempt is retried by the next window
+static WINDOW_CLASS_REGISTERED: Mutex<bool> = Mutex::new(false);
+
+// All windows share one class; registering it a second time would fail.
+fn register_window_class(instance: HINSTANCE) -> Result<()> {
+    // Held across the registration so two threads cannot both register the class
+    let mut registered = WINDOW_CLASS_REGISTERED
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner);
+    if *registered {
+        return Ok(());
+    }
+    let class_name = WideCString::try_from(WINDOW_CLASS_NAME)?;
+    unsafe {
+        let wc = WNDCLASSW {
+            hCursor: LoadCursorW(None, IDC_CROSS),
+            hInstance: instance,
+            lpszClassName: class_name.as_pcwstr().as_pwstr(),
+
+            style: CS_HREDRAW | CS_VREDRAW,
+            lpfnWndProc: Some(Window::wndproc),
+            ..Default::default()
+        };
+        if RegisterClassW(&wc) == 0 {
+            return Err(win_error!(Error::from_win32()));
+        }
+    }
+    *registered = true;
+    Ok(())
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Fullscreen {
+    /// Undecorated window covering the monitor it is on, without a display mode change.
+    Borderless,
+    /// DXGI exclusive fullscreen on the current output with the given display mode.
+    Exclusive(DisplayMode),
+}
+
 pub struct Window {
-    width: i32,
-    height: i32,
+    windowed_style: u32,
+    position: Option<(i32, i32)>,
+    style: u32,
+    ex_style: u32,
+    icon: Option<Icon>,
+    small_icon: Option<Icon>,
     window_name: String,
     window_handle: HWND,
-    pub visible: bool,
-    kbd: Keyboard,
-    mouse: Mouse,
-    pub gfx: Option<Graphics>, 
+    main: bool,
+    occluded: bool,
+    accept_files: bool,
+    file_drop_error: Option<Win32Error>,
+    state: Rc<WindowState>,
+}
+
+/// The part of a window the window procedure works on.
+///
+/// Messages also arrive while the application is inside a call on the window, e.g.
+/// `SetWindowPos` sends `WM_SIZE` and a modal loop dispatches everything, so the window
+/// procedure never gets to the `Window`. It reaches this state instead, which the HWND
+/// keeps alive, and only ever shares it. No `RefCell` stays borrowed across a call that
+/// may send a message.
+pub(crate) struct WindowState {
+    window_handle: Cell<HWND>,
+    width: Cell<i32>,
+    height: Cell<i32>,
+    min_size: Cell<Option<(i32, i32)>>,
+    max_size: Cell<Option<(i32, i32)>>,
+    fullscreen: Cell<Option<Fullscreen>>,
+    fullscreen_toggle: Cell<Option<Fullscreen>>,
+    windowed_placement: Cell<WINDOWPLACEMENT>,
+    placement_file: RefCell<Option<PathBuf>>,
+    destroyed: Cell<bool>,
+    minimized: Cell<bool>,
+    focused: Cell<bool>,
+    drop_target: RefCell<Option<DropRegistration>>,
+    hooks: MessageHooks,
+    // Failures in the window procedure, held until the message pump returns
+    message_error: RefCell<Option<Win32Error>>,
+    message_panic: RefCell<Option<Box<dyn Any + Send>>>,
+    kbd: RefCell<Keyboard>,
+    mouse: RefCell<Mouse>,
+    events: RefCell<VecDeque<WindowEvent>>,
+    gfx: RefCell<Option<Graphics>>,
 }
 
 impl Window {
     pub fn new(width: i32, height: i32, window_user_name: &str) -> Window {
         Window {
-            width,
-            height,
+            windowed_style: WS_OVERLAPPEDWINDOW | WS_VISIBLE,
+            position: None,
+            style: WS_OVERLAPPEDWINDOW | WS_VISIBLE,
+            ex_style: 0,
+            icon: None,
+            small_icon: None,
             window_name: window_user_name.into(),
             window_handle: 0,
-            visible: false, // will need to be set on actual window creation
-            kbd: Keyboard::new(),
-            mouse: Mouse::new(),
-            gfx: None,
+            main: true,
+            occluded: false,
+            accept_files: true,
+            file_drop_error: None,
+            state: Rc::new(WindowState {
+                window_handle: Cell::new(0),
+                width: Cell::new(width),
+                height: Cell::new(height),
+                min_size: Cell::new(None),
+                max_size: Cell::new(None),
+                fullscreen: Cell::new(None),
+                fullscreen_toggle: Cell::new(Some(Fullscreen::Borderless)),
+                windowed_placement: Cell::new(WINDOWPLACEMENT::default()),
+                placement_file: RefCell::new(None),
+                destroyed: Cell::new(false),
+                minimized: Cell::new(false),
+                focused: Cell::new(false),
+                drop_target: RefCell::new(None),
+                hooks: MessageHooks::default(),
+                message_error: RefCell::new(None),
+                message_panic: RefCell::new(None),
+                kbd: RefCell::new(Keyboard::new()),
+                mouse: RefCell::new(Mouse::new()),
+                events: RefCell::new(VecDeque::new()),
+                gfx: RefCell::new(None),
+            }),
         }
     }
 
-    pub fn initialize(&mut self) -> Result<()> {
-        unsafe {
-            let instance = GetModuleHandleW(None);
-            let window_class_name = "window".to_wide().as_ptr() as *mut u16;
+    pub(crate) fn from_builder(builder: WindowBuilder) -> Window {
+        let (width, height) = builder.size;
+        let mut window = Window::new(width, height, &builder.title);
+        window.state.min_size.set(builder.min_size);
+        window.state.max_size.set(builder.max_size);
+        window.position = builder.position;
+        window.main = builder.main;
+        window.icon = builder.icon;
+        window.small_icon = builder.small_icon;
+        window.accept_files = builder.accept_files;
+        *window.state.placement_file.borrow_mut() = builder.placement_file;
+        for hook in builder.message_hooks {
+            window.state.hooks.add(hook);
+        }
 
-            let wc = {
-                WNDCLASSW {
-                    hCursor: LoadCursorW(None, IDC_CROSS),
-                    hInstance: instance,
-                    lpszClassName: PWSTR(window_class_name),
+        window.style = if builder.decorated {
+            WS_OVERLAPPEDWINDOW
+        } else {
+            WS_POPUP
+        };
+        if builder.decorated && !builder.resizable {
+            window.style &= !(WS_THICKFRAME | WS_MAXIMIZEBOX);
+        }
+        if builder.visible {
+            window.style |= WS_VISIBLE;
+        }
+        if builder.always_on_top {
+            window.ex_style |= WS_EX_TOPMOST;
+        }
+        window.windowed_style = window.style;
+        window
+    }
 
-                    style: CS_HREDRAW | CS_VREDRAW,
-                    lpfnWndProc: Some(Self::wndproc),
-                    ..Default::default()
-                }
-            };
+    /// Creates the OS window for `window`.
+    pub(crate) fn create(mut window: Window) -> Result<Window> {
+        window.initialize()?;
+        // Creating the window already sends messages, e.g. WM_CREATE and WM_SIZE
+        window.take_message_failure()?;
+        Ok(window)
+    }
+
+    fn initialize(&mut self) -> Result<()> {
+        unsafe {
+            let instance = GetModuleHandleW(None);
+            ENABLE_DPI_AWARENESS.call_once(|| {
+                // Fails if the awareness was already set, e.g. by the application manifest
+                SetProcessDpiAwarenessContext(DPI_AWARENESS_CONTEXT_PER_MONITOR_AWARE_V2);
+            });
+            register_window_class(instance)?;
 
-            let atom = RegisterClassW(&wc);
-            debug_assert!(atom != 0);
+            // A saved placement from a previous run overrides the configured position and size
+            let placement = self
+                .state
+                .placement_file
+                .borrow()
+                .as_deref()
+                .and_then(WindowPlacement::load)
+                .map(|placement| {
+                    // Without the monitor layout the placement is used as saved
+                    placement.fit_to_monitors(&monitor::monitors().unwrap_or_default())
+                });
 
             let window_handle = {
-                // calculate window size based on desired client region size
-                let mut wr = RECT::default();
-                wr.left = 100;
-                wr.right = self.width + wr.left;
-                wr.top = 100;
-                wr.bottom = self.height + wr.top;
-                // Adjust window size to accomodate the desired client dimensions specified by `width` and `height`.
-                AdjustWindowRect(&mut wr, WS_CAPTION | WS_MINIMIZEBOX | WS_SYSMENU, BOOL(0))
-                    .ok()
-                    .map_err(|e| win_error!(e))?;
-                let window_name: &str = &self.window_name;
+                let (x, y, width, height) = match &placement {
+                    Some(placement) => (placement.x, placement.y, placement.width, placement.height),
+                    None => {
+                        // calculate window size based on desired client region size
+                        let mut wr = RECT {
+                            left: 0,
+                            top: 0,
+                            right: self.state.width.get(),
+                            bottom: self.state.height.get(),
+                        };
+                        // Adjust window size to accomodate the desired client dimensions specified by `width` and `height`.
+                        AdjustWindowRectEx(&mut wr, self.style, BOOL(0), self.ex_style)
+                            .ok()
+                            .map_err(|e| win_error!(e))?;
+                        let (x, y) = self.position.unwrap_or((CW_USEDEFAULT, CW_USEDEFAULT));
+                        (x, y, wr.right - wr.left, wr.bottom - wr.top)
+                    }
+                };
+                let class_name = WideCString::try_from(WINDOW_CLASS_NAME)?;
+                let window_name = WideCString::try_from(&self.window_name)?;
                 CreateWindowExW(
-                    Default::default(),
-                    PWSTR(window_class_name),
-                    PWSTR(window_name.to_wide().as_ptr() as *mut u16),
-                    WS_OVERLAPPEDWINDOW | WS_VISIBLE,
-                    CW_USEDEFAULT,
-                    CW_USEDEFAULT,
-                    wr.right - wr.left,
-                    wr.bottom - wr.top,
+                    self.ex_style,
+                    &class_name,
+                    &window_name,
+                    self.style,
+                    x,
+                    y,
+                    width,
+                    height,
                     None,
                     None,
                     instance,
-                    self as *mut Window as *const c_void,
+                    Rc::as_ptr(&self.state) as *const c_void,
                 )
             };
 
-            
-            // Check for error
-            debug_assert!(window_handle != 0);
-            debug_assert!(window_handle == self.window_handle);
+            if window_handle == 0 {
+                return Err(win_error!(Error::from_win32()));
+            }
+            debug_assert!(window_handle == self.state.window_handle.get());
+            self.window_handle = window_handle;
+
+            self.state
+                .mouse
+                .borrow_mut()
+                .on_dpi_changed(GetDpiForWindow(window_handle));
+
+            self.apply_icons();
+
+            // Delivers WM_CLIPBOARDUPDATE; the listener is removed with the window
+            AddClipboardFormatListener(window_handle)
+                .ok()
+                .map_err(|e| win_error!(e))?;
+
+            if self.accept_files {
+                // Dropping files is not worth failing the window over, see `file_drop_error`
+                match drop_target::register(window_handle) {
+                    Ok(registration) => {
+                        *self.state.drop_target.borrow_mut() = Some(registration)
+                    }
+                    Err(e) => self.file_drop_error = Some(e),
+                }
+            }
+
+            // Hidden windows get their first WM_SIZE only when shown, so query the real client size
+            let mut rect = RECT::default();
+            GetClientRect(window_handle, &mut rect)
+                .ok()
+                .map_err(|e| win_error!(e))?;
+            let (width, height) = (rect.right - rect.left, rect.bottom - rect.top);
+            self.state.width.set(width);
+            self.state.height.set(height);
+            self.state
+                .mouse
+                .borrow_mut()
+                .on_client_resize(width, height);
 
             // Create graphics object
-            self.gfx = Some(Graphics::new(window_handle)?);
+            let gfx = Graphics::new(window_handle, width as u32, height as u32)?;
+            *self.state.gfx.borrow_mut() = Some(gfx);
+
+            if let Some(placement) = placement {
+                // Maximizing also shows the window, so hidden windows stay restored
+                if placement.maximized && self.is_visible() {
+                    ShowWindow(window_handle, SW_MAXIMIZE);
+                }
+                if placement.fullscreen {
+                    self.set_fullscreen(Some(Fullscreen::Borderless))?;
+                }
+            }
 
             Ok(())
         }
     }
 
-    fn render(&mut self) -> Result<()> {
-        // TEST KBD CODE
-        if self.kbd.key_is_pressed(VK_MENU) {
+    pub fn id(&self) -> WindowId {
+        WindowId(self.window_handle)
+    }
+
+    pub fn is_initialized(&self) -> bool {
+        self.window_handle != 0
+    }
+
+    pub fn is_destroyed(&self) -> bool {
+        self.state.destroyed.get()
+    }
+
+    /// Monitor the window is on, or the one nearest to it.
+    pub fn current_monitor(&self) -> Result<Monitor> {
+        unsafe {
+            monitor::monitor(MonitorFromWindow(
+                self.window_handle,
+                MONITOR_DEFAULTTONEAREST,
+            ))
+        }
+    }
+
+    /// Centers the window on `monitor`, shrinking it to the work area if needed. A
+    /// fullscreen window leaves and re-enters fullscreen on the new monitor.
+    pub fn move_to_monitor(&mut self, monitor: &Monitor) -> Result<()> {
+        let fullscreen = self.state.fullscreen.get();
+        if fullscreen.is_some() {
+            self.set_fullscreen(None)?;
+        }
+        if self.is_maximized() {
+            self.restore();
+        }
+        unsafe {
+            let mut rect = RECT::default();
+            GetWindowRect(self.window_handle, &mut rect)
+                .ok()
+                .map_err(|e| win_error!(e))?;
+            let area = monitor.work_area;
+            let width = (rect.right - rect.left).min(area.width());
+            let height = (rect.bottom - rect.top).min(area.height());
+            SetWindowPos(
+                self.window_handle,
+                None,
+                area.left + (area.width() - width) / 2,
+                area.top + (area.height() - height) / 2,
+                width,
+                height,
+                SWP_NOZORDER | SWP_NOACTIVATE,
+            )
+            .ok()
+            .map_err(|e| win_error!(e))?;
+        }
+        if fullscreen.is_some() {
+            self.set_fullscreen(fullscreen)?;
+        }
+        Ok(())
+    }
+
+    /// Current placement, as it would be saved to the placement file.
+    pub fn placement(&self) -> Result<WindowPlacement> {
+        self.state.placement()
+    }
+
+    /// Asks the window to close as if the user clicked its close button. The request
+    /// arrives as `WindowEvent::CloseRequested` and can be vetoed.
+    pub fn request_close(&self) -> Result<()> {
+        if !self.is_initialized() || self.is_destroyed() {
+            return Ok(());
+        }
+        unsafe {
+            PostMessageW(self.window_handle, WM_CLOSE, 0, 0)
+                .ok()
+                .map_err(|e| win_error!(e))
+        }
+    }
+
+    /// Destroys the window right away, without a `CloseRequested` event. The graphics
+    /// are released first, while the window they render to still exists.
+    pub fn close(&mut self) -> Result<()> {
+        self.state.gfx.replace(None);
+        if !self.is_initialized() || self.is_destroyed() {
+            return Ok(());
+        }
+        unsafe {
+            DestroyWindow(self.window_handle)
+                .ok()
+                .map_err(|e| win_error!(e))
+        }
+    }
+
+    /// The application keeps running while at least one main window is open; closing the
+    /// last one also closes the secondary windows such as tool palettes.
+    pub fn is_main(&self) -> bool {
+        self.main
+    }
+
+    pub fn set_main(&mut self, main: bool) {
+        self.main = main;
+    }
+
+    pub fn title(&self) -> String {
+        if !self.is_initialized() {
+            return self.window_name.clone();
+        }
+        unsafe {
+            let length = GetWindowTextLengthW(self.window_handle);
+            let mut buffer = vec![0u16; length as usize + 1];
+            let copied = GetWindowTextW(
+                self.window_handle,
+                PWSTR(buffer.as_mut_ptr()),
+                buffer.len() as i32,
+            );
+            String::from_utf16_lossy(&buffer[..copied as usize])
+        }
+    }
+
+    pub fn set_title(&mut self, title: &str) -> Result<()> {
+        let wide_title = WideCString::try_from(title)?;
+        self.window_name = title.into();
+        if self.is_initialized() {
             unsafe {
-                MessageBoxW(
-                    0,
-                    PWSTR("Message Received!".to_wide().as_ptr() as *mut u16),
-                    PWSTR("ALT Key Pressed!".to_wide().as_ptr() as *mut u16),
-                    MB_OK,
-                );
+                SetWindowTextW(self.window_handle, &wide_title)
+                    .ok()
+                    .map_err(|e| win_error!(e))?;
             }
         }
+        Ok(())
+    }
 
-        // TEST MOUSE CODE
-        while !self.mouse.is_empty() {
-            if let Some(event) = self.mouse.read() {
-                if event.get_type() == crate::mouse::EventType::Move {
-                    println!(
-                        "Mouse Position: {}, {}",
-                        event.get_pos_x(),
-                        event.get_pos_y()
-                    );
-                }
+    /// Current client area size in physical pixels, as reported by the OS.
+    pub fn size(&self) -> (i32, i32) {
+        if !self.is_initialized() {
+            return (self.state.width.get(), self.state.height.get());
+        }
+        let mut rect = RECT::default();
+        unsafe {
+            GetClientRect(self.window_handle, &mut rect);
+        }
+        (rect.right - rect.left, rect.bottom - rect.top)
+    }
+
+    /// Ratio between physical pixels and logical units on the window's current monitor.
+    pub fn scale_factor(&self) -> f32 {
+        if !self.is_initialized() {
+            return 1.0;
+        }
+        unsafe { scale_factor_for_dpi(GetDpiForWindow(self.window_handle)) }
+    }
+
+    pub fn logical_size(&self) -> LogicalSize {
+        let (width, height) = self.size();
+        PhysicalSize::new(width, height).to_logical(self.scale_factor())
+    }
+
+    pub fn set_logical_size(&mut self, size: LogicalSize) -> Result<()> {
+        let size = size.to_physical(self.scale_factor());
+        self.set_size(size.width, size.height)
+    }
+
+    /// Resizes the window so its client area has the given size in physical pixels.
+    pub fn set_size(&mut self, width: i32, height: i32) -> Result<()> {
+        if !self.is_initialized() {
+            self.state.width.set(width);
+            self.state.height.set(height);
+            return Ok(());
+        }
+        let (window_width, window_height) =
+            window_size_for_client(self.window_handle, width, height);
+        unsafe {
+            SetWindowPos(
+                self.window_handle,
+                None,
+                0,
+                0,
+                window_width,
+                window_height,
+                SWP_NOMOVE | SWP_NOZORDER | SWP_NOACTIVATE,
+            )
+            .ok()
+            .map_err(|e| win_error!(e))
+        }
+    }
+
+    /// Screen position of the outer window's top left corner. `None` until the OS has
+    /// placed a window created without an explicit position.
+    pub fn position(&self) -> Option<(i32, i32)> {
+        if !self.is_initialized() {
+            return self.position;
+        }
+        let mut rect = RECT::default();
+        unsafe {
+            GetWindowRect(self.window_handle, &mut rect);
+        }
+        Some((rect.left, rect.top))
+    }
+
+    pub fn set_position(&mut self, x: i32, y: i32) -> Result<()> {
+        self.position = Some((x, y));
+        if !self.is_initialized() {
+            return Ok(());
+        }
+        unsafe {
+            SetWindowPos(
+                self.window_handle,
+                None,
+                x,
+                y,
+                0,
+                0,
+                SWP_NOSIZE | SWP_NOZORDER | SWP_NOACTIVATE,
+            )
+            .ok()
+            .map_err(|e| win_error!(e))
+        }
+    }
+
+    pub fn minimize(&mut self) {
+        self.show_window(SW_MINIMIZE);
+    }
+
+    pub fn maximize(&mut self) {
+        self.show_window(SW_MAXIMIZE);
+    }
+
+    /// Restores a minimized or maximized window to its normal size and position.
+    pub fn restore(&mut self) {
+        self.show_window(SW_RESTORE);
+    }
+
+    pub fn show(&mut self) {
+        self.style |= WS_VISIBLE;
+        self.show_window(SW_SHOW);
+    }
+
+    pub fn hide(&mut self) {
+        self.style &= !WS_VISIBLE;
+        self.show_window(SW_HIDE);
+    }
+
+    pub fn is_minimized(&self) -> bool {
+        self.is_initialized() && unsafe { IsIconic(self.window_handle).as_bool() }
+    }
+
+    pub fn is_maximized(&self) -> bool {
+        self.is_initialized() && unsafe { IsZoomed(self.window_handle).as_bool() }
+    }
+
+    pub fn is_visible(&self) -> bool {
+        if !self.is_initialized() {
+            return self.style & WS_VISIBLE != 0;
+        }
+        unsafe { IsWindowVisible(self.window_handle).as_bool() }
+    }
+
+    /// Whether the window has keyboard focus.
+    pub fn is_focused(&self) -> bool {
+        self.state.focused.get()
+    }
+
+    /// Whether files can be dragged onto the window, see `WindowBuilder::accept_files`.
+    pub fn accepts_file_drops(&self) -> bool {
+        self.state.drop_target.borrow().is_some()
+    }
+
+    /// Why the window does not accept files although `WindowBuilder::accept_files` asked
+    /// for it.
+    pub fn file_drop_error(&self) -> Option<&Win32Error> {
+        self.file_drop_error.as_ref()
+    }
+
+    /// Whether the last present found the window completely covered, e.g. by another
+    /// fullscreen window or the lock screen.
+    pub fn is_occluded(&self) -> bool {
+        self.occluded
+    }
+
+    /// Whether a rendered frame could currently be seen by the user.
+    pub fn can_render(&self) -> bool {
+        self.is_visible() && !self.state.minimized.get() && !self.occluded
+    }
+
+    /// Sets the icon shown in the taskbar and the Alt+Tab switcher, also used in the
+    /// title bar unless a small icon is set. `None` restores the default icon.
+    pub fn set_icon(&mut self, icon: Option<Icon>) {
+        // The previous icons are destroyed only after the window stopped using them
+        let _previous = std::mem::replace(&mut self.icon, icon);
+        self.apply_icons();
+    }
+
+    /// Sets the icon shown in the title bar, see `Icon::small_size`.
+    pub fn set_small_icon(&mut self, icon: Option<Icon>) {
+        let _previous = std::mem::replace(&mut self.small_icon, icon);
+        self.apply_icons();
+    }
+
+    fn apply_icons(&self) {
+        if !self.is_initialized() {
+            return;
+        }
+        let big = self.icon.as_ref().map_or(0, Icon::handle);
+        let small = self
+            .small_icon
+            .as_ref()
+            .or(self.icon.as_ref())
+            .map_or(0, Icon::handle);
+        unsafe {
+            SendMessageW(self.window_handle, WM_SETICON, ICON_BIG as usize, big);
+            SendMessageW(self.window_handle, WM_SETICON, ICON_SMALL as usize, small);
+        }
+    }
+
+    /// Installs a hook that sees every message the window receives from `WM_NCCREATE` on,
+    /// before the window handles it, and may consume it. Hooks run in the order they
+    /// were added; hooks added through `WindowBuilder::message_hook` come first and also
+    /// see the messages sent while the window is created.
+    pub fn add_message_hook<F>(&mut self, hook: F) -> HookId
+    where
+        F: FnMut(&RawMessage) -> HookResult + 'static,
+    {
+        self.state.hooks.add(Box::new(hook))
+    }
+
+    /// Returns `false` if no such hook is installed.
+    pub fn remove_message_hook(&mut self, id: HookId) -> bool {
+        self.state.hooks.remove(id)
+    }
+
+    /// Returns the first error raised while handling a message since the last call, or
+    /// resumes the panic caught while handling one.
+    pub(crate) fn take_message_failure(&mut self) -> Result<()> {
+        self.state.take_message_failure()
+    }
+
+    /// Handles a message the way the window procedure does, `None` meaning the
+    /// default handling. Only for messages whose handling makes no OS calls.
+    #[cfg(test)]
+    pub(crate) fn handle_test_message(
+        &self,
+        message: u32,
+        wparam: WPARAM,
+        lparam: LPARAM,
+    ) -> Option<LRESULT> {
+        self.state.handle_message(message, wparam, lparam)
+    }
+
+    pub(crate) fn set_occluded(&mut self, occluded: bool) {
+        if occluded != self.occluded {
+            self.occluded = occluded;
+            self.state.push_event(WindowEvent::Occluded(occluded));
+        }
+    }
+
+    fn show_window(&self, command: u32) {
+        if self.is_initialized() {
+            // The return value is the previous visibility, not an error indicator
+            unsafe {
+                ShowWindow(self.window_handle, command);
             }
         }
+    }
+
+    /// Smallest client area size the user can resize the window to.
+    pub fn set_min_size(&mut self, size: Option<(i32, i32)>) {
+        self.state.min_size.set(size);
+    }
 
+    /// Largest client area size the user can resize the window to.
+    pub fn set_max_size(&mut self, size: Option<(i32, i32)>) {
+        self.state.max_size.set(size);
+    }
+
+    pub fn fullscreen(&self) -> Option<Fullscreen> {
+        self.state.fullscreen.get()
+    }
+
+    /// Switches between windowed (`None`) and the fullscreen modes, restoring the
+    /// previous window style and placement when returning to windowed.
+    pub fn set_fullscreen(&mut self, fullscreen: Option<Fullscreen>) -> Result<()> {
+        if self.fullscreen() == fullscreen {
+            return Ok(());
+        }
+        match self.fullscreen() {
+            None => self.save_windowed_placement()?,
+            Some(Fullscreen::Exclusive(_)) => {
+                self.gfx_mut()?.set_exclusive_fullscreen(None)?;
+                self.restore_windowed_placement()?;
+            }
+            Some(Fullscreen::Borderless) => self.restore_windowed_placement()?,
+        }
+        match fullscreen {
+            None => {}
+            Some(Fullscreen::Borderless) => self.enter_borderless()?,
+            Some(Fullscreen::Exclusive(mode)) => {
+                self.gfx_mut()?.set_exclusive_fullscreen(Some(mode))?
+            }
+        }
+        self.state.fullscreen.set(fullscreen);
         Ok(())
     }
 
-    fn user_message_handler(&mut self, message: u32, wparam: WPARAM, lparam: LPARAM) -> LRESULT {
+    /// Mode entered by the Alt+Enter hotkey; `None` disables the hotkey.
+    pub fn set_fullscreen_toggle(&mut self, fullscreen: Option<Fullscreen>) {
+        self.state.fullscreen_toggle.set(fullscreen);
+    }
+
+    /// Leaves fullscreen, or enters the mode configured with `set_fullscreen_toggle`.
+    pub fn toggle_fullscreen(&mut self) -> Result<()> {
+        match self.fullscreen() {
+            Some(_) => self.set_fullscreen(None),
+            None => self.set_fullscreen(self.state.fullscreen_toggle.get()),
+        }
+    }
+
+    fn gfx_mut(&mut self) -> Result<RefMut<'_, Graphics>> {
+        self.graphics_mut()
+            .ok_or_else(|| win_error!(DXGI_ERROR_INVALID_CALL.into()))
+    }
+
+    fn save_windowed_placement(&mut self) -> Result<()> {
+        unsafe {
+            self.windowed_style = GetWindowLongW(self.window_handle, GWL_STYLE) as u32;
+            let mut windowed_placement = WINDOWPLACEMENT {
+                length: std::mem::size_of::<WINDOWPLACEMENT>() as u32,
+                ..Default::default()
+            };
+            GetWindowPlacement(self.window_handle, &mut windowed_placement)
+                .ok()
+                .map_err(|e| win_error!(e))?;
+            self.state.windowed_placement.set(windowed_placement);
+            Ok(())
+        }
+    }
+
+    fn restore_windowed_placement(&mut self) -> Result<()> {
         unsafe {
-            match message {
-                WM_ACTIVATE => {
-                    self.visible = true;
-                    0
+            SetWindowLongW(self.window_handle, GWL_STYLE, self.windowed_style as i32);
+            SetWindowPlacement(self.window_handle, &self.state.windowed_placement.get())
+                .ok()
+                .map_err(|e| win_error!(e))?;
+            // Style changes only take effect on the frame after SWP_FRAMECHANGED
+            SetWindowPos(
+                self.window_handle,
+                None,
+                0,
+                0,
+                0,
+                0,
+                SWP_FRAMECHANGED | SWP_NOMOVE | SWP_NOSIZE | SWP_NOZORDER | SWP_NOOWNERZORDER,
+            )
+            .ok()
+            .map_err(|e| win_error!(e))
+        }
+    }
+
+    fn enter_borderless(&mut self) -> Result<()> {
+        unsafe {
+            let monitor = MonitorFromWindow(self.window_handle, MONITOR_DEFAULTTONEAREST);
+            let mut info = MONITORINFO {
+                cbSize: std::mem::size_of::<MONITORINFO>() as u32,
+                ..Default::default()
+            };
+            GetMonitorInfoW(monitor, &mut info)
+                .ok()
+                .map_err(|e| win_error!(e))?;
+            SetWindowLongW(
+                self.window_handle,
+                GWL_STYLE,
+                (WS_POPUP | WS_VISIBLE) as i32,
+            );
+            let rect = info.rcMonitor;
+            SetWindowPos(
+                self.window_handle,
+                HWND_TOP,
+                rect.left,
+                rect.top,
+                rect.right - rect.left,
+                rect.bottom - rect.top,
+                SWP_FRAMECHANGED | SWP_NOOWNERZORDER,
+            )
+            .ok()
+            .map_err(|e| win_error!(e))
+        }
+    }
+
+    pub fn read_event(&mut self) -> Option<WindowEvent> {
+        self.state.events.borrow_mut().pop_front()
+    }
+
+    /// Takes the queued events; events arriving meanwhile wait for the next call.
+    pub fn events(&mut self) -> impl Iterator<Item = WindowEvent> {
+        self.state.events.take().into_iter()
+    }
+
+    // The window procedure updates the input devices while messages are dispatched, so
+    // borrows of them must end before the next message pump or modal loop, or the
+    // window procedure panics.
+
+    /// Key state queries and auto repeat settings. Key and char events are delivered
+    /// through the window's event queue.
+    pub fn keyboard(&self) -> Ref<'_, Keyboard> {
+        self.state.kbd.borrow()
+    }
+
+    pub fn keyboard_mut(&mut self) -> RefMut<'_, Keyboard> {
+        self.state.kbd.borrow_mut()
+    }
+
+    /// Mouse state queries, filters and hit-test regions. Mouse events are delivered
+    /// through the window's event queue.
+    pub fn mouse(&self) -> Ref<'_, Mouse> {
+        self.state.mouse.borrow()
+    }
+
+    pub fn mouse_mut(&mut self) -> RefMut<'_, Mouse> {
+        self.state.mouse.borrow_mut()
+    }
+
+    pub fn graphics(&self) -> Option<Ref<'_, Graphics>> {
+        Ref::filter_map(self.state.gfx.borrow(), Option::as_ref).ok()
+    }
+
+    pub fn graphics_mut(&mut self) -> Option<RefMut<'_, Graphics>> {
+        RefMut::filter_map(self.state.gfx.borrow_mut(), Option::as_mut).ok()
+    }
+
+    /// Text on the clipboard, `None` if it holds no text.
+    pub fn clipboard_text(&self) -> Result<Option<String>> {
+        clipboard::get_text(self.window_handle)
+    }
+
+    pub fn set_clipboard_text(&self, text: &str) -> Result<()> {
+        clipboard::set_text(self.window_handle, text)
+    }
+
+    /// Image on the clipboard, `None` if it holds no image in a supported format.
+    pub fn clipboard_image(&self) -> Result<Option<Image>> {
+        clipboard::get_image(self.window_handle)
+    }
+
+    pub fn set_clipboard_image(&self, image: &Image) -> Result<()> {
+        clipboard::set_image(self.window_handle, image)
+    }
+
+    extern "system" fn wndproc(
+        window_handle: HWND,
+        message: u32,
+        wparam: WPARAM,
+        lparam: LPARAM,
+    ) -> LRESULT {
+        if message == WM_NCCREATE {
+            unsafe {
+                let cs = lparam as *const CREATESTRUCTW;
+                attach(window_handle, (*cs).lpCreateParams as *const WindowState);
+            }
+        }
+        // Only windows of the class this procedure is registered for get here
+        let result = unsafe {
+            dispatch(window_handle, |state| {
+                state.handle_message(message, wparam, lparam)
+            })
+        }
+        .flatten();
+        // The last message of the HWND, which lets go of the state
+        if message == WM_NCDESTROY {
+            detach(window_handle);
+        }
+        result.unwrap_or_else(|| unsafe { DefWindowProcW(window_handle, message, wparam, lparam) })
+    }
+}
+
+impl WindowState {
+    fn placement(&self) -> Result<WindowPlacement> {
+        unsafe {
+            // While fullscreen, the placement to restore is the one saved when entering it
+            let windowed_placement = if self.fullscreen.get().is_some() {
+                self.windowed_placement.get()
+            } else {
+                let mut windowed_placement = WINDOWPLACEMENT {
+                    length: std::mem::size_of::<WINDOWPLACEMENT>() as u32,
+                    ..Default::default()
+                };
+                GetWindowPlacement(self.window_handle.get(), &mut windowed_placement)
+                    .ok()
+                    .map_err(|e| win_error!(e))?;
+                windowed_placement
+            };
+            // Only the work area is needed, the display modes of `current_monitor` would
+            // make closing the window depend on DXGI
+            let monitor = monitor::monitor_area(MonitorFromWindow(
+                self.window_handle.get(),
+                MONITOR_DEFAULTTONEAREST,
+            ))?;
+
+            // The normal position is in workspace coordinates, relative to the work area
+            let rect = windowed_placement.rcNormalPosition;
+            let offset_x = monitor.work_area.left - monitor.bounds.left;
+            let offset_y = monitor.work_area.top - monitor.bounds.top;
+            Ok(WindowPlacement {
+                x: rect.left + offset_x,
+                y: rect.top + offset_y,
+                width: rect.right - rect.left,
+                height: rect.bottom - rect.top,
+                maximized: windowed_placement.showCmd == SW_SHOWMAXIMIZED,
+                fullscreen: self.fullscreen.get() == Some(Fullscreen::Borderless),
+                monitor: monitor.name,
+            })
+        }
+    }
+
+    fn take_message_failure(&self) -> Result<()> {
+        if let Some(payload) = self.message_panic.take() {
+            panic::resume_unwind(payload);
+        }
+        match self.message_error.take() {
+            Some(error) => Err(error),
+            None => Ok(()),
+        }
+    }
+
+    pub(crate) fn push_event(&self, event: WindowEvent) {
+        self.events.borrow_mut().push_back(event);
+    }
+
+    // Moves the events buffered by the input devices into the window's queue, keeping
+    // them in order with the other events of the message that produced them.
+    fn forward_input_events(&self) {
+        let mut events = self.events.borrow_mut();
+        let mut kbd = self.kbd.borrow_mut();
+        while let Some(event) = kbd.read_key() {
+            events.push_back(WindowEvent::Key(event));
+        }
+        while let Some(character) = kbd.read_char() {
+            events.push_back(WindowEvent::Char(character));
+        }
+        let mut mouse = self.mouse.borrow_mut();
+        while let Some(event) = mouse.read() {
+            events.push_back(WindowEvent::Mouse(event));
+        }
+    }
+
+    fn user_message_handler(
+        &self,
+        message: u32,
+        wparam: WPARAM,
+        lparam: LPARAM,
+    ) -> Result<Option<LRESULT>> {
+        unsafe {
+            Ok(match message {
+                WM_SIZE => {
+                    // Low and high word of lparam contain the new client area size
+                    let width = (lparam & 0xFFFF) as i32;
+                    let height = ((lparam >> 16) & 0xFFFF) as i32;
+                    self.width.set(width);
+                    self.height.set(height);
+                    self.mouse.borrow_mut().on_client_resize(width, height);
+                    self.push_event(WindowEvent::Resized { width, height });
+                    // wparam tells whether the window was minimized, maximized or restored
+                    let minimized = wparam as u32 == SIZE_MINIMIZED;
+                    if minimized != self.minimized.replace(minimized) {
+                        self.push_event(WindowEvent::Minimized(minimized));
+                    }
+                    Some(0)
+                }
+
+                WM_DPICHANGED => {
+                    // Low word of wparam has the new DPI, lparam points to the suggested window rectangle
+                    let dpi = (wparam & 0xFFFF) as u32;
+                    let rect = *(lparam as *const RECT);
+                    self.mouse.borrow_mut().on_dpi_changed(dpi);
+                    SetWindowPos(
+                        self.window_handle.get(),
+                        None,
+                        rect.left,
+                        rect.top,
+                        rect.right - rect.left,
+                        rect.bottom - rect.top,
+                        SWP_NOZORDER | SWP_NOACTIVATE,
+                    )
+                    .ok()
+                    .map_err(|e| win_error!(e))?;
+                    let mut client = RECT::default();
+                    GetClientRect(self.window_handle.get(), &mut client);
+                    let (width, height) = (client.right - client.left, client.bottom - client.top);
+                    self.push_event(WindowEvent::ScaleFactorChanged {
+                        scale_factor: scale_factor_for_dpi(dpi),
+                        size: PhysicalSize::new(width, height),
+                    });
+                    Some(0)
                 }
 
+                WM_GETMINMAXINFO => {
+                    let info = &mut *(lparam as *mut MINMAXINFO);
+                    if let Some((width, height)) = self.min_size.get() {
+                        let (x, y) =
+                            window_size_for_client(self.window_handle.get(), width, height);
+                        info.ptMinTrackSize = POINT { x, y };
+                    }
+                    if let Some((width, height)) = self.max_size.get() {
+                        let (x, y) =
+                            window_size_for_client(self.window_handle.get(), width, height);
+                        info.ptMaxTrackSize = POINT { x, y };
+                    }
+                    Some(0)
+                }
+
+                WM_SYSKEYDOWN
+                    if wparam == VK_RETURN as usize
+                        // bit 29: ALT is held, bit 30: key was already down (autorepeat)
+                        && lparam & 0x20000000 != 0
+                        && self.fullscreen_toggle.get().is_some() =>
+                {
+                    if lparam & 0x40000000 == 0 {
+                        self.push_event(WindowEvent::FullscreenToggleRequested);
+                    }
+                    Some(0)
+                }
+
+                // Swallow the matching WM_SYSCHAR so Alt+Enter does not trigger the error beep
+                WM_SYSCHAR if wparam == VK_RETURN as usize => Some(0),
+
                 WM_KEYDOWN | WM_SYSKEYDOWN => {
                     // filter for autorepeat key messages to decide whether to process a key press or not.
-                    if lparam & 0x40000000 == 0 || self.kbd.auto_repeat_is_enabled() {
-                        self.kbd.on_key_pressed(
-                            wparam
-                                .try_into()
-                                .expect("failed to convert keycode to u8"),
-                        );
+                    if lparam & 0x40000000 == 0 || self.kbd.borrow().auto_repeat_is_enabled() {
+                        if let Some(key) = virtual_key(wparam)? {
+                            self.kbd.borrow_mut().on_key_pressed(key);
+                        }
+                    }
+                    // The OS acts on system keys too, e.g. Alt+F4 closes and F10 opens the menu
+                    if message == WM_SYSKEYDOWN {
+                        None
+                    } else {
+                        Some(0)
                     }
-                    0
                 }
 
                 WM_KEYUP | WM_SYSKEYUP => {
-                    self.kbd
-                        .on_key_released(wparam.try_into().expect("failed to convert keycode"));
-                    0
+                    if let Some(key) = virtual_key(wparam)? {
+                        self.kbd.borrow_mut().on_key_released(key);
+                    }
+                    if message == WM_SYSKEYUP {
+                        None
+                    } else {
+                        Some(0)
+                    }
                 }
 
                 WM_CHAR => {
-                    self.kbd
-                        .on_char(wparam.try_into().expect("failed to convert char"));
-                    0
+                    let character = u16::try_from(wparam).map_err(|_| {
+                        Win32Error::invalid_argument("WM_CHAR code unit out of range")
+                    })?;
+                    self.kbd.borrow_mut().on_char(character);
+                    Some(0)
+                }
+
+                WM_SETFOCUS => {
+                    self.focused.set(true);
+                    self.push_event(WindowEvent::Focused(true));
+                    Some(0)
                 }
 
                 WM_KILLFOCUS => {
-                    self.kbd.clear_state();
-                    0
+                    self.focused.set(false);
+                    self.kbd.borrow_mut().clear_state();
+                    self.push_event(WindowEvent::Focused(false));
+                    Some(0)
                 }
 
                 WM_MOUSEMOVE => {
-                    // First 16-bits of lparam contain mouse x-position
-                    let x = lparam & 0xFFFF;
-                    // Next 16-bits of lparam contain mouse y-position
-                    let y = (lparam >> 16) & 0xFFFF;
-
-                    // Mouse inside client area
-                    if x >= 0 && y >= 0 && x < self.width as isize && y < self.height as isize {
-                        self.mouse.on_mouse_move(x, y);
-                        if !self.mouse.is_in_window() {
-                            // Still receive mouse move events when we leave the window client area
-                            SetCapture(self.window_handle);
-                            self.mouse.on_mouse_enter();
+                    // Low and high word of lparam contain the signed client-area position;
+                    // it goes negative when captured and dragged left of/above the window.
+                    let x = get_x_lparam(lparam) as isize;
+                    let y = get_y_lparam(lparam) as isize;
+
+                    // Capture changes send WM_CAPTURECHANGED, so they wait until the
+                    // mouse is no longer borrowed
+                    let mut capture = None;
+                    {
+                        let mut mouse = self.mouse.borrow_mut();
+                        // Mouse inside client area
+                        if mouse.is_in_client_area(x, y) {
+                            mouse.on_mouse_move(x, y);
+                            if !mouse.is_in_window() {
+                                mouse.on_mouse_enter();
+                                capture = Some(true);
+                            }
+                        }
+                        // Mouse outside client area
+                        else {
+                            // track mouse when left or right button is pressed (dragging)
+                            if mouse.left_is_pressed() || mouse.right_is_pressed() {
+                                mouse.on_mouse_move(x, y);
+                            }
+                            // Don't track mouse when leaving the client area
+                            else {
+                                mouse.on_mouse_leave();
+                                capture = Some(false);
+                            }
                         }
                     }
-                    // Mouse outside client area
-                    else {
-                        // track mouse when left or right button is pressed (dragging)
-                        if self.mouse.left_is_pressed() || self.mouse.right_is_pressed() {
-                            self.mouse.on_mouse_move(x, y);
+                    match capture {
+                        // Still receive mouse move events when we leave the window client area
+                        Some(true) => {
+                            SetCapture(self.window_handle.get());
                         }
-                        // Don't track mouse when leaving the client area
-                        else {
+                        Some(false) => {
                             ReleaseCapture();
-                            self.mouse.on_mouse_leave();
                         }
+                        None => {}
                     }
-                    0
+                    Some(0)
                 }
 
                 WM_LBUTTONDOWN => {
-                    self.mouse.on_left_pressed();
-                    0
+                    self.mouse.borrow_mut().on_left_pressed();
+                    Some(0)
                 }
 
                 WM_RBUTTONDOWN => {
-                    self.mouse.on_right_pressed();
-                    0
+                    self.mouse.borrow_mut().on_right_pressed();
+                    Some(0)
                 }
 
                 WM_LBUTTONUP => {
-                    self.mouse.on_left_released();
-                    0
+                    self.mouse.borrow_mut().on_left_released();
+                    Some(0)
                 }
 
                 WM_RBUTTONUP => {
-                    self.mouse.on_right_released();
-                    0
+                    self.mouse.borrow_mut().on_right_released();
+                    Some(0)
+                }
+
+                WM_MOUSEWHEEL => {
+                    // Wheel messages carry the cursor position in screen coordinates
+                    let mut point = POINT {
+                        x: get_x_lparam(lparam),
+                        y: get_y_lparam(lparam),
+                    };
+                    ScreenToClient(self.window_handle.get(), &mut point);
+                    self.mouse.borrow_mut().on_wheel_delta(
+                        point.x as isize,
+                        point.y as isize,
+                        get_wheel_delta_wparam(wparam),
+                    );
+                    Some(0)
+                }
+
+                WM_CLIPBOARDUPDATE => {
+                    self.push_event(WindowEvent::ClipboardChanged);
+                    Some(0)
                 }
 
-                WM_MOUSEHWHEEL => {
-                    // First 16-bits of lparam contain mouse x-position
-                    let x = lparam & 0xFFFF;
-                    // Next 16-bits of lparam contain mouse y-position
-                    let y = (lparam >> 16) & 0xFFFF;
-                    self.mouse.on_wheel_delta(x, y, wparam);
-                    0
+                // Closing is up to the application, see `WindowEvent::CloseRequested`
+                WM_CLOSE => {
+                    self.push_event(WindowEvent::CloseRequested);
+                    Some(0)
                 }
 
                 WM_DESTROY => {
-                    PostQuitMessage(0);
-                    0
+                    // Reported once the window is torn down, which must happen regardless
+                    let placement_file = self.placement_file.borrow().clone();
+                    let saved = match placement_file {
+                        Some(path) => self.placement().and_then(|placement| placement.save(&path)),
+                        None => Ok(()),
+                    };
+                    // Also reached when the OS destroys the window without `close`, e.g. with
+                    // its owner; the swap chain must not outlive the window. If the
+                    // application holds the graphics right now, `close` releases them.
+                    let gfx = self.gfx.try_borrow_mut().map(|mut gfx| gfx.take());
+                    drop(gfx);
+                    let drop_target = self.drop_target.take();
+                    drop(drop_target);
+                    // The application decides when to quit, based on which windows remain
+                    self.destroyed.set(true);
+                    self.push_event(WindowEvent::Destroyed);
+                    saved?;
+                    Some(0)
                 }
-                _ => DefWindowProcW(self.window_handle, message, wparam, lparam),
-            }
+                _ => None,
+            })
         }
     }
 
-    extern "system" fn wndproc(
-        window_handle: HWND,
-        message: u32,
-        wparam: WPARAM,
-        lparam: LPARAM,
-    ) -> LRESULT {
-        unsafe {
-            if message == WM_NCCREATE {
-                let cs = lparam as *const CREATESTRUCTW;
-                let this = (*cs).lpCreateParams as *mut Self;
-                (*this).window_handle = window_handle;
-                SetWindowLongPtrW(window_handle, GWLP_USERDATA, this as isize);
-            } else {
-                let this = GetWindowLongPtrW(window_handle, GWLP_USERDATA) as *mut Self;
-                if !this.is_null() {
-                    return (*this).user_message_handler(message, wparam, lparam);
-                }
+    /// Runs the hooks and the window's own handling; `None` asks for the default
+    /// handling.
+    fn handle_message(&self, message: u32, wparam: WPARAM, lparam: LPARAM) -> Option<LRESULT> {
+        self.catch_failure(|| {
+            if let HookResult::Consume(result) =
+                self.hooks
+                    .call(self.window_handle.get(), message, wparam, lparam)
+            {
+                return Ok(Some(result));
             }
+            let result = self.user_message_handler(message, wparam, lparam);
+            self.forward_input_events();
+            result
+        })
+    }
 
-            DefWindowProcW(window_handle, message, wparam, lparam)
+    /// Runs `handler` for a message, `None` meaning the default handling. Neither errors
+    /// nor panics may cross the window procedure, so they are stored for
+    /// `take_message_failure` and the message gets the default handling instead.
+    ///
+    /// OLE callbacks on the window's behalf go through here too.
+    pub(crate) fn catch_failure<T>(
+        &self,
+        handler: impl FnOnce() -> Result<Option<T>>,
+    ) -> Option<T> {
+        // After a panic the window may be inconsistent until the panic is resumed
+        if self.message_panic.borrow().is_some() {
+            return None;
+        }
+        match panic::catch_unwind(AssertUnwindSafe(handler)) {
+            Ok(Ok(result)) => result,
+            Ok(Err(error)) => {
+                // Later errors are usually consequences of the first
+                self.message_error.borrow_mut().get_or_insert(error);
+                None
+            }
+            Err(payload) => {
+                // A panic in a nested message happened first
+                self.message_panic.borrow_mut().get_or_insert(payload);
+                None
+            }
         }
     }
 }
 
+// Outer window size needed for a client area of the given size with the current style.
+fn window_size_for_client(window_handle: HWND, width: i32, height: i32) -> (i32, i32) {
+    let mut rect = RECT {
+        left: 0,
+        top: 0,
+        right: width,
+        bottom: height,
+    };
+    unsafe {
+        // Frame metrics depend on the DPI of the monitor the window is on
+        AdjustWindowRectExForDpi(
+            &mut rect,
+            GetWindowLongW(window_handle, GWL_STYLE) as u32,
+            BOOL(0),
+            GetWindowLongW(window_handle, GWL_EXSTYLE) as u32,
+            GetDpiForWindow(window_handle),
+        );
+    }
+    (rect.right - rect.left, rect.bottom - rect.top)
+}
+
+/// Virtual-key code of a key message; `None` for 0xFF, which keys without a virtual-key
+/// mapping report.
+fn virtual_key(wparam: WPARAM) -> Result<Option<u16>> {
+    match u8::try_from(wparam) {
+        Ok(0xFF) => Ok(None),
+        Ok(key) => Ok(Some(key as u16)),
+        Err(_) => Err(Win32Error::invalid_argument("virtual-key code out of range")),
+    }
+}
+
+/// Gives the HWND a strong reference to the window state, on `WM_NCCREATE`.
+///
+/// # Safety
+///
+/// `state` must come from `Rc::as_ptr` on a state that is alive for the call, see
+/// `Window::initialize`.
+unsafe fn attach(window_handle: HWND, state: *const WindowState) {
+    Rc::increment_strong_count(state);
+    (*state).window_handle.set(window_handle);
+    SetWindowLongPtrW(window_handle, GWLP_USERDATA, state as isize);
+}
+
+/// Releases the reference taken by `attach`, on `WM_NCDESTROY`.
+fn detach(window_handle: HWND) {
+    unsafe {
+        let state = SetWindowLongPtrW(window_handle, GWLP_USERDATA, 0) as *const WindowState;
+        if !state.is_null() {
+            drop(Rc::from_raw(state));
+        }
+    }
+}
+
+/// Runs `f` on the state of the window that owns `window_handle`. Returns `None` before
+/// `WM_NCCREATE` and after `WM_NCDESTROY`.
+///
+/// `f` holds a reference of its own, so the state outlives it even if the window is
+/// destroyed and dropped meanwhile. Messages sent while one is handled, e.g. `WM_SIZE`
+/// from `SetWindowPos`, nest inside `f`.
+///
+/// # Safety
+///
+/// `window_handle` must belong to this crate's window class, whose `GWLP_USERDATA` slot
+/// holds nothing but the state. Other windows may store anything there.
+pub(crate) unsafe fn dispatch<R>(
+    window_handle: HWND,
+    f: impl FnOnce(&WindowState) -> R,
+) -> Option<R> {
+    let state = GetWindowLongPtrW(window_handle, GWLP_USERDATA) as *const WindowState;
+    if state.is_null() {
+        return None;
+    }
+    Rc::increment_strong_count(state);
+    let state = Rc::from_raw(state);
+    Some(f(&state))
+}
+
 impl Drop for Window {
     fn drop(&mut self) {
-        unsafe {
-            if self.window_handle != 0 {
-                println!("Destroying window.");
-                let _ = DestroyWindow(self.window_handle)
-                    .ok()
-                    .map_err(|e| println!("{}", win_error!(e))); // TODO: error triggers on exit!?
-            }
+        // Teardown failures are reported by `close` and `App::run`; whatever is left here
+        // has no one to go to and is discarded, a panic included
+        if self.window_handle != 0 && !self.is_destroyed() {
+            let _ = self.close();
         }
+        self.state.message_error.take();
+        self.state.message_panic.take();
+        // If DestroyWindow failed, e.g. on another thread, the HWND keeps the state alive
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use windows::Win32::UI::Input::KeyboardAndMouse::{VK_F4, VK_SPACE};
+
+    use super::*;
+
+    // Bit 29 of a key message's lparam: ALT is held
+    const ALT_DOWN: LPARAM = 0x2000_0000;
+
+    #[test]
+    fn system_keys_reach_default_handling() {
+        let window = Window::new(640, 480, "test");
+        // Alt+F4 is recorded and still left to the OS, which turns it into WM_CLOSE
+        let handled = window.handle_test_message(WM_SYSKEYDOWN, VK_F4 as usize, ALT_DOWN);
+        assert_eq!(handled, None);
+        assert!(window.keyboard().key_is_pressed(VK_F4));
+        let handled = window.handle_test_message(WM_SYSKEYUP, VK_F4 as usize, ALT_DOWN);
+        assert_eq!(handled, None);
+        assert!(!window.keyboard().key_is_pressed(VK_F4));
+
+        let handled = window.handle_test_message(WM_KEYDOWN, VK_SPACE as usize, 0);
+        assert_eq!(handled, Some(0));
+        let handled = window.handle_test_message(WM_KEYUP, VK_SPACE as usize, 0);
+        assert_eq!(handled, Some(0));
+    }
+
+    #[test]
+    fn dropping_discards_message_failures() {
+        let window = Window::new(640, 480, "test");
+        window.handle_test_message(WM_CHAR, 0x1_0000, 0);
+        drop(window);
+
+        let mut window = Window::new(640, 480, "test");
+        window.add_message_hook(|_| -> HookResult { panic!("handler failed") });
+        window.handle_test_message(WM_CHAR, 'a' as usize, 0);
+        drop(window);
+    }
+
+    #[test]
+    fn alt_enter_requests_the_fullscreen_toggle() {
+        let mut window = Window::new(640, 480, "test");
+        let handled = window.handle_test_message(WM_SYSKEYDOWN, VK_RETURN as usize, ALT_DOWN);
+        assert_eq!(handled, Some(0));
+        let handled = window.handle_test_message(WM_SYSCHAR, VK_RETURN as usize, ALT_DOWN);
+        assert_eq!(handled, Some(0));
+        let events: Vec<_> = window.events().collect();
+        assert!(matches!(
+            events[..],
+            [WindowEvent::FullscreenToggleRequested]
+        ));
+
+        // Without a toggle mode Alt+Enter is an ordinary system key
+        window.set_fullscreen_toggle(None);
+        let handled = window.handle_test_message(WM_SYSKEYDOWN, VK_RETURN as usize, ALT_DOWN);
+        assert_eq!(handled, None);
     }
 }
@@ -0,0 +1,284 @@
+use std::path::PathBuf;
+
+use crate::error::Win32Error;
+use crate::icon::Icon;
+use crate::message_hook::{Hook, HookResult, RawMessage};
+use crate::window::Window;
+pub type Result<T> = core::result::Result<T, Win32Error>;
+
+/// Configuration for a `Window`, checked by `build` before any OS window is created.
+///
+/// Sizes are client area sizes in physical pixels, positions are the screen
+/// coordinates of the outer window's top left corner.
+pub struct WindowBuilder {
+    pub(crate) title: String,
+    pub(crate) size: (i32, i32),
+    pub(crate) position: Option<(i32, i32)>,
+    pub(crate) resizable: bool,
+    pub(crate) decorated: bool,
+    pub(crate) always_on_top: bool,
+    pub(crate) min_size: Option<(i32, i32)>,
+    pub(crate) max_size: Option<(i32, i32)>,
+    pub(crate) visible: bool,
+    pub(crate) main: bool,
+    pub(crate) icon: Option<Icon>,
+    pub(crate) small_icon: Option<Icon>,
+    pub(crate) accept_files: bool,
+    pub(crate) placement_file: Option<PathBuf>,
+    pub(crate) message_hooks: Vec<Hook>,
+}
+
+impl WindowBuilder {
+    pub fn new() -> WindowBuilder {
+        WindowBuilder {
+            title: String::new(),
+            size: (800, 600),
+            position: None,
+            resizable: true,
+            decorated: true,
+            always_on_top: false,
+            min_size: None,
+            max_size: None,
+            visible: true,
+            main: true,
+            icon: None,
+            small_icon: None,
+            accept_files: true,
+            placement_file: None,
+            message_hooks: Vec::new(),
+        }
+    }
+
+    pub fn title(mut self, title: &str) -> WindowBuilder {
+        self.title = title.into();
+        self
+    }
+
+    pub fn size(mut self, width: i32, height: i32) -> WindowBuilder {
+        self.size = (width, height);
+        self
+    }
+
+    /// Defaults to letting the OS choose (`CW_USEDEFAULT`).
+    pub fn position(mut self, x: i32, y: i32) -> WindowBuilder {
+        self.position = Some((x, y));
+        self
+    }
+
+    pub fn resizable(mut self, resizable: bool) -> WindowBuilder {
+        self.resizable = resizable;
+        self
+    }
+
+    /// Without decorations the window has no title bar or border.
+    pub fn decorated(mut self, decorated: bool) -> WindowBuilder {
+        self.decorated = decorated;
+        self
+    }
+
+    pub fn always_on_top(mut self, always_on_top: bool) -> WindowBuilder {
+        self.always_on_top = always_on_top;
+        self
+    }
+
+    pub fn min_size(mut self, width: i32, height: i32) -> WindowBuilder {
+        self.min_size = Some((width, height));
+        self
+    }
+
+    pub fn max_size(mut self, width: i32, height: i32) -> WindowBuilder {
+        self.max_size = Some((width, height));
+        self
+    }
+
+    pub fn visible(mut self, visible: bool) -> WindowBuilder {
+        self.visible = visible;
+        self
+    }
+
+    /// See `Window::is_main`.
+    pub fn main(mut self, main: bool) -> WindowBuilder {
+        self.main = main;
+        self
+    }
+
+    /// See `Window::set_icon`.
+    pub fn icon(mut self, icon: Icon) -> WindowBuilder {
+        self.icon = Some(icon);
+        self
+    }
+
+    /// See `Window::set_small_icon`.
+    pub fn small_icon(mut self, icon: Icon) -> WindowBuilder {
+        self.small_icon = Some(icon);
+        self
+    }
+
+    /// Whether files can be dragged onto the window, see `WindowEvent::FileDropped`.
+    /// Where OLE cannot be initialized on the thread, e.g. in the multithreaded
+    /// apartment, the window is created without accepting files, see
+    /// `Window::file_drop_error`.
+    pub fn accept_files(mut self, accept_files: bool) -> WindowBuilder {
+        self.accept_files = accept_files;
+        self
+    }
+
+    /// Saves the window's placement to `path` when it is destroyed and restores it from
+    /// there when it is created, in place of `position` and `size`.
+    pub fn placement_file(mut self, path: impl Into<PathBuf>) -> WindowBuilder {
+        self.placement_file = Some(path.into());
+        self
+    }
+
+    /// Installs a message hook before the OS window is created, see
+    /// `Window::add_message_hook`.
+    pub fn message_hook<F>(mut self, hook: F) -> WindowBuilder
+    where
+        F: FnMut(&RawMessage) -> HookResult + 'static,
+    {
+        self.message_hooks.push(Box::new(hook));
+        self
+    }
+
+    /// Validates the configuration. The OS window is created once the window is
+    /// added to the `App`.
+    pub fn build(self) -> Result<Window> {
+        self.validate()?;
+        Ok(Window::from_builder(self))
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.title.contains('\0') {
+            return Err(Win32Error::invalid_argument(
+                "window title contains a nul character",
+            ));
+        }
+        if !is_positive(self.size) {
+            return Err(Win32Error::invalid_argument("window size must be positive"));
+        }
+        if let Some(min_size) = self.min_size {
+            if !is_positive(min_size) {
+                return Err(Win32Error::invalid_argument(
+                    "minimum window size must be positive",
+                ));
+            }
+        }
+        if let Some(max_size) = self.max_size {
+            if !is_positive(max_size) {
+                return Err(Win32Error::invalid_argument(
+                    "maximum window size must be positive",
+                ));
+            }
+        }
+        // Checked before the size, which would otherwise always violate one of the limits
+        if let (Some(min_size), Some(max_size)) = (self.min_size, self.max_size) {
+            if min_size.0 > max_size.0 || min_size.1 > max_size.1 {
+                return Err(Win32Error::invalid_argument(
+                    "minimum window size exceeds the maximum size",
+                ));
+            }
+        }
+        if let Some(min_size) = self.min_size {
+            if self.size.0 < min_size.0 || self.size.1 < min_size.1 {
+                return Err(Win32Error::invalid_argument(
+                    "window size is below the minimum size",
+                ));
+            }
+        }
+        if let Some(max_size) = self.max_size {
+            if self.size.0 > max_size.0 || self.size.1 > max_size.1 {
+                return Err(Win32Error::invalid_argument(
+                    "window size exceeds the maximum size",
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Default for WindowBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn is_positive((width, height): (i32, i32)) -> bool {
+    width > 0 && height > 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use windows::Win32::Foundation::E_INVALIDARG;
+
+    fn assert_rejected(builder: WindowBuilder, reason: &str) {
+        let error = builder.validate().expect_err(reason);
+        assert_eq!(error.code(), E_INVALIDARG);
+        assert!(error.to_string().ends_with(reason), "{}", error);
+    }
+
+    #[test]
+    fn accepts_a_size_within_the_limits() {
+        let builder = WindowBuilder::new()
+            .size(800, 600)
+            .min_size(800, 600)
+            .max_size(1024, 768);
+        assert!(builder.validate().is_ok());
+        assert!(WindowBuilder::new().size(1, 1).validate().is_ok());
+    }
+
+    #[test]
+    fn rejects_zero_and_negative_sizes() {
+        let reason = "window size must be positive";
+        assert_rejected(WindowBuilder::new().size(0, 600), reason);
+        assert_rejected(WindowBuilder::new().size(800, 0), reason);
+        assert_rejected(WindowBuilder::new().size(-800, 600), reason);
+        assert_rejected(
+            WindowBuilder::new().min_size(0, 0),
+            "minimum window size must be positive",
+        );
+        assert_rejected(
+            WindowBuilder::new().max_size(1024, 0),
+            "maximum window size must be positive",
+        );
+    }
+
+    #[test]
+    fn rejects_a_size_outside_the_limits() {
+        assert_rejected(
+            WindowBuilder::new().size(800, 600).min_size(640, 700),
+            "window size is below the minimum size",
+        );
+        assert_rejected(
+            WindowBuilder::new().size(800, 600).max_size(1024, 500),
+            "window size exceeds the maximum size",
+        );
+    }
+
+    #[test]
+    fn rejects_a_minimum_above_the_maximum() {
+        let reason = "minimum window size exceeds the maximum size";
+        assert_rejected(
+            WindowBuilder::new()
+                .size(800, 600)
+                .min_size(1024, 768)
+                .max_size(640, 480),
+            reason,
+        );
+        assert_rejected(
+            WindowBuilder::new()
+                .size(800, 600)
+                .min_size(640, 700)
+                .max_size(1024, 650),
+            reason,
+        );
+    }
+
+    #[test]
+    fn rejects_a_title_with_a_nul() {
+        assert_rejected(
+            WindowBuilder::new().title("a\0b"),
+            "window title contains a nul character",
+        );
+    }
+}